@@ -0,0 +1,111 @@
+use image::RgbaImage;
+use serde::Deserialize;
+
+use super::*;
+
+// How far the grain of the Noise background moves each channel either way, out of 255
+const NOISE_AMOUNT: f32 = 6.0;
+
+// The background is a small image that's stretched over the canvas, a gradient only needs
+// one column of it. The grain is a tile repeated across the canvas a pixel for a pixel.
+const GRADIENT_ROWS: u32 = 256;
+const GRAIN_TILE: u32 = 256;
+
+// The shifting gradient is kept dark so the lines still stand out against it. The bottom
+// colour is further round the colour wheel than the top by the hue spread in degrees.
+const SHIFTING_SATURATION: f64 = 0.7;
+const SHIFTING_TOP_VALUE: f64 = 0.18;
+const SHIFTING_BOTTOM_VALUE: f64 = 0.06;
+const SHIFTING_HUE_SPREAD: f64 = 60.0;
+
+// What the canvas is painted with underneath the lines and what it fades back towards
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+pub enum Background {
+    Solid([f32; 4]), // one colour everywhere, black is the original look
+    // From the top of the screen to the bottom
+    VerticalGradient([f32; 4], [f32; 4]),
+    // Dark colours that drift round the colour wheel taking this many seconds to go round
+    ShiftingGradient(f64),
+    Noise([f32; 4]), // a colour with a faint fixed grain like paper
+}
+
+impl Background {
+    // Only a background that changes needs painting again as time goes on
+    pub fn is_changing(&self) -> bool {
+        matches!(self, Background::ShiftingGradient(_))
+    }
+
+    // The width and height of the image the background is painted on
+    pub fn image_size(&self) -> (u32, u32) {
+        match self {
+            Background::Noise(_) => (GRAIN_TILE, GRAIN_TILE),
+            _ => (1, GRADIENT_ROWS),
+        }
+    }
+
+    // Whether the image is repeated across the canvas rather than stretched over it
+    pub fn is_tiled(&self) -> bool {
+        matches!(self, Background::Noise(_))
+    }
+
+    // Paints the background as it looks seconds after the screensaver started
+    pub fn paint(&self, image: &mut RgbaImage, seconds: f64) {
+        let (top, bottom) = match *self {
+            Background::Solid(colour) | Background::Noise(colour) => (colour, colour),
+            Background::VerticalGradient(top, bottom) => (top, bottom),
+            Background::ShiftingGradient(seconds_per_turn) => {
+                let hue = seconds / seconds_per_turn * 360.0;
+                (
+                    hsv_to_rgb(hue, SHIFTING_SATURATION, SHIFTING_TOP_VALUE),
+                    hsv_to_rgb(
+                        hue + SHIFTING_HUE_SPREAD,
+                        SHIFTING_SATURATION,
+                        SHIFTING_BOTTOM_VALUE,
+                    ),
+                )
+            }
+        };
+
+        // the colour only changes down the screen so it's worked out once per row and only
+        // the grain is added pixel by pixel
+        let grainy = matches!(self, Background::Noise(_));
+        let (width, height) = image.dimensions();
+        let last_row = (height.max(2) - 1) as f32;
+        let row_bytes = width as usize * 4;
+        for (y, row) in image.chunks_exact_mut(row_bytes.max(1)).enumerate() {
+            let down = y as f32 / last_row;
+            let mut colour = [0.0; 3];
+            for channel in 0..3 {
+                colour[channel] = (top[channel] + (bottom[channel] - top[channel]) * down) * 255.0;
+            }
+
+            let to_byte = |value: f32| value.round().clamp(0.0, 255.0) as u8;
+            let plain = [
+                to_byte(colour[0]),
+                to_byte(colour[1]),
+                to_byte(colour[2]),
+                255,
+            ];
+            for (x, pixel) in row.chunks_exact_mut(4).enumerate() {
+                if grainy {
+                    let noise = grain(x as u32, y as u32) * NOISE_AMOUNT;
+                    for channel in 0..3 {
+                        pixel[channel] = to_byte(colour[channel] + noise);
+                    }
+                    pixel[3] = 255;
+                } else {
+                    pixel.copy_from_slice(&plain);
+                }
+            }
+        }
+    }
+}
+
+// A random looking number in -1..1 that is always the same for the same pixel
+fn grain(x: u32, y: u32) -> f32 {
+    let mut hash = x.wrapping_mul(0x9e3779b1) ^ y.wrapping_mul(0x85ebca77);
+    hash ^= hash >> 15;
+    hash = hash.wrapping_mul(0x2c1b3c6d);
+    hash ^= hash >> 12;
+    hash as f32 / u32::MAX as f32 * 2.0 - 1.0
+}
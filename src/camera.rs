@@ -0,0 +1,117 @@
+use graphics::math::Matrix2d;
+use graphics::Transformed;
+use rand::prelude::*;
+use serde::Deserialize;
+
+use super::*;
+
+// What fraction of the way to where it wants to be the camera moves in a second
+const CAMERA_EASING: f64 = 0.3;
+
+// Seconds between the camera picking somewhere new to drift to
+const SECONDS_PER_DRIFT: f64 = 15.0;
+
+// The closest the camera gets, one pixel of the plane to one pixel on the screen. Any
+// closer and the lines would start to look blurry.
+const MAX_ZOOM: f64 = 1.0;
+
+// Where the camera looks on the plane the turtles draw on
+#[derive(Copy, Clone, Debug, Deserialize)]
+pub enum CameraMode {
+    Fixed,  // the whole plane shrunk to fit on the screen
+    Drift,  // wander to a random place and zoom every so often
+    Follow, // keep the first turtle in the middle of the screen while the zoom drifts
+}
+
+pub struct Camera {
+    centre: [f64; 2], // on the plane
+    zoom: f64,        // screen pixels per pixel of the plane
+    target_centre: [f64; 2],
+    target_zoom: f64,
+    seconds_to_next_drift: f64,
+}
+
+impl Camera {
+    pub fn new(world: Size, screen: Size) -> Self {
+        let centre = [world.width / 2.0, world.height / 2.0];
+        let zoom = min_zoom(world, screen);
+        Camera {
+            centre,
+            zoom,
+            target_centre: centre,
+            target_zoom: zoom,
+            seconds_to_next_drift: 0.0,
+        }
+    }
+
+    // Eases the camera towards where the mode wants it. lead is where the first turtle is
+    // in the same coordinates as its segments.
+    pub fn update(
+        &mut self,
+        dt: f64,
+        mode: CameraMode,
+        world: Size,
+        screen: Size,
+        lead: Option<[f64; 2]>,
+        rng: &mut ThreadRng,
+    ) {
+        let min_zoom = min_zoom(world, screen);
+        self.seconds_to_next_drift -= dt;
+        if self.seconds_to_next_drift <= 0.0 {
+            self.seconds_to_next_drift = SECONDS_PER_DRIFT;
+            self.target_centre = [
+                rng.gen_range(0.0, world.width),
+                rng.gen_range(0.0, world.height),
+            ];
+
+            // zooming by the same factor should happen just as often wherever it starts
+            self.target_zoom = if min_zoom < MAX_ZOOM {
+                rng.gen_range(min_zoom.ln(), MAX_ZOOM.ln()).exp()
+            } else {
+                min_zoom
+            };
+        }
+
+        match mode {
+            CameraMode::Fixed => {
+                self.target_centre = [world.width / 2.0, world.height / 2.0];
+                self.target_zoom = min_zoom;
+            }
+            CameraMode::Drift => {}
+            CameraMode::Follow => {
+                if let Some(lead) = lead {
+                    self.target_centre = lead;
+                }
+            }
+        }
+
+        let easing = 1.0 - (1.0 - CAMERA_EASING).powf(dt);
+        for axis in 0..2 {
+            self.centre[axis] += (self.target_centre[axis] - self.centre[axis]) * easing;
+        }
+        self.zoom *= (self.target_zoom / self.zoom).powf(easing);
+        self.zoom = self.zoom.clamp(min_zoom, MAX_ZOOM.max(min_zoom));
+
+        // never look past the edges of the plane
+        let view = [screen.width, screen.height];
+        let plane = [world.width, world.height];
+        for axis in 0..2 {
+            let half_view = view[axis] / self.zoom / 2.0;
+            let furthest = (plane[axis] - half_view).max(half_view);
+            self.centre[axis] = self.centre[axis].clamp(half_view, furthest);
+        }
+    }
+
+    // Takes points on the plane to points on the screen
+    pub fn view(&self, transform: Matrix2d, screen: Size) -> Matrix2d {
+        transform
+            .trans(screen.width / 2.0, screen.height / 2.0)
+            .zoom(self.zoom)
+            .trans(-self.centre[0], -self.centre[1])
+    }
+}
+
+// The zoom that just fits the whole plane on the screen
+fn min_zoom(world: Size, screen: Size) -> f64 {
+    (screen.width / world.width).max(screen.height / world.height)
+}
@@ -0,0 +1,553 @@
+use gl::types::{GLenum, GLint, GLuint};
+use graphics::math::Matrix2d;
+use graphics::triangulation::{tx, ty};
+use image::RgbaImage;
+use opengl_graphics::shader_utils::{compile_shader, uniform_location, DynamicAttribute};
+use opengl_graphics::GlGraphics;
+use piston::window::Size;
+use rand::prelude::*;
+use serde::Deserialize;
+use std::ffi::CString;
+use std::ptr;
+
+use super::background::Background;
+use super::segment_batch::{BatchShader, SegmentBatch};
+
+// The canvas is split into this many cells along each axis when looking for an empty area
+const DENSITY_CELLS: usize = 12;
+
+// Draws a texture over a quad, turning the displayed colours of the background into the
+// light that displays as them, or tone mapping light for display
+const QUAD_VERTEX_SHADER: &str = "
+#version 150 core
+in vec2 pos;
+in vec2 uv;
+out vec2 v_uv;
+
+void main() {
+    v_uv = uv;
+    gl_Position = vec4(pos, 0.0, 1.0);
+}
+";
+
+const QUAD_FRAGMENT_SHADER: &str = "
+#version 150 core
+uniform sampler2D s_texture;
+uniform float alpha;
+uniform int conversion;
+uniform float exposure;
+in vec2 v_uv;
+out vec4 o_colour;
+
+void main() {
+    vec3 colour = texture(s_texture, v_uv).rgb;
+    if (conversion == 1) {
+        colour = colour / (1.0 - min(colour, 0.999)) / exposure;
+    } else if (conversion == 2) {
+        colour *= exposure;
+        colour = colour / (1.0 + colour);
+    }
+    o_colour = vec4(colour, alpha);
+}
+";
+
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+pub enum BlendMode {
+    Alpha,    // paint over what is already there
+    Additive, // add light so overlapping lines glow like a long exposure photo
+}
+
+// How the quad shader changes the colours of the texture it draws
+#[derive(Copy, Clone)]
+enum Conversion {
+    None = 0,
+    ToLight = 1, // Reinhard tone mapping undone so the light displays as the texture does
+    ToColour = 2, // Reinhard tone mapping squeezes any amount of light into 0..1
+}
+
+// A persistent off-screen texture on the graphics card that everything is drawn onto.
+// Nothing is ever cleared between frames so the drawing builds up here and the whole
+// canvas is copied to the screen every frame. Fading and clearing draw the background
+// over it, a little or all the way.
+//
+// The canvas holds linear light in half floats. In Additive mode there can be any amount
+// of light in a pixel and it's only tone mapped on its way to the screen so bright
+// overlaps saturate smoothly towards white instead of clipping. Exposure is applied then
+// too. In Alpha mode the extra precision lets fades creep all the way back to the
+// background instead of leaving faint ghosts of old lines behind.
+pub struct Canvas {
+    width: u32,
+    height: u32,
+    world: Size, // the plane the canvas covers
+    blend_mode: BlendMode,
+    exposure: f32,
+    framebuffer: GLuint,
+    texture: GLuint,
+    background: GLuint,
+    background_image: RgbaImage,
+    background_repeats: [f32; 2], // how many times the background goes across and down
+    quad: Quad,
+    density: Density,
+}
+
+impl Canvas {
+    pub fn new(
+        graphics: &mut GlGraphics,
+        world: Size,
+        width: u32,
+        height: u32,
+        blend_mode: BlendMode,
+        exposure: f32,
+        background: &Background,
+    ) -> Self {
+        let (image_width, image_height) = background.image_size();
+        let mut background_image = RgbaImage::new(image_width, image_height);
+        background.paint(&mut background_image, 0.0);
+        let (background_wrap, background_repeats) = if background.is_tiled() {
+            let repeats = [
+                width as f32 / image_width as f32,
+                height as f32 / image_height as f32,
+            ];
+            (gl::REPEAT, repeats)
+        } else {
+            (gl::CLAMP_TO_EDGE, [1.0, 1.0])
+        };
+
+        let (texture, background, framebuffer) = unsafe {
+            let texture = create_texture(
+                gl::RGBA16F,
+                width,
+                height,
+                gl::CLAMP_TO_EDGE,
+                gl::FLOAT,
+                ptr::null(),
+            );
+            let background = create_texture(
+                gl::SRGB8_ALPHA8,
+                image_width,
+                image_height,
+                background_wrap,
+                gl::UNSIGNED_BYTE,
+                background_image.as_ptr() as *const _,
+            );
+
+            let mut framebuffer = 0;
+            gl::GenFramebuffers(1, &mut framebuffer);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, framebuffer);
+            gl::FramebufferTexture2D(
+                gl::FRAMEBUFFER,
+                gl::COLOR_ATTACHMENT0,
+                gl::TEXTURE_2D,
+                texture,
+                0,
+            );
+            let status = gl::CheckFramebufferStatus(gl::FRAMEBUFFER);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+            assert_eq!(
+                status,
+                gl::FRAMEBUFFER_COMPLETE,
+                "the graphics card can't draw onto a {} x {} canvas",
+                width,
+                height
+            );
+
+            (texture, background, framebuffer)
+        };
+
+        let mut canvas = Canvas {
+            width,
+            height,
+            world,
+            blend_mode,
+            exposure,
+            framebuffer,
+            texture,
+            background,
+            background_image,
+            background_repeats,
+            quad: Quad::new(),
+            density: Density::new(),
+        };
+
+        canvas.clear(graphics);
+        canvas
+    }
+
+    // Paints the background that the canvas fades towards, seconds after starting
+    pub fn set_background(&mut self, background: &Background, seconds: f64) {
+        background.paint(&mut self.background_image, seconds);
+        let (width, height) = self.background_image.dimensions();
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, self.background);
+            gl::TexSubImage2D(
+                gl::TEXTURE_2D,
+                0,
+                0,
+                0,
+                width as i32,
+                height as i32,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                self.background_image.as_ptr() as *const _,
+            );
+        }
+    }
+
+    // Wipes everything off the canvas leaving just the background
+    pub fn clear(&mut self, graphics: &mut GlGraphics) {
+        self.density.clear();
+        self.cover(graphics, 1.0);
+    }
+
+    // Blends alpha of the background over everything
+    pub fn fade(&mut self, graphics: &mut GlGraphics, alpha: f32) {
+        self.density.fade(alpha);
+        self.cover(graphics, alpha);
+    }
+
+    // Returns a random point in the cell with the least drawn in it as a fraction of the
+    // canvas size
+    pub fn least_dense_point(&self, rng: &mut ThreadRng) -> [f64; 2] {
+        self.density.least_dense_point(rng)
+    }
+
+    // Draws the batch onto the canvas, in the same coordinates as the plane
+    pub fn draw(&mut self, graphics: &mut GlGraphics, shader: &BatchShader, batch: &SegmentBatch) {
+        self.density.add(batch, self.world);
+
+        // the texture is the other way up to the screen, the first row is at the bottom
+        let transform = [
+            [2.0 / self.world.width, 0.0, -1.0],
+            [0.0, 2.0 / self.world.height, -1.0],
+        ];
+
+        // light is added up without being multiplied by the exposure, that's done along
+        // with the tone mapping
+        self.bind();
+        batch.submit(shader, transform, self.blend_mode, graphics);
+        unbind();
+    }
+
+    // Copies the canvas to the screen, transform takes the plane to the screen. Must be
+    // called while graphics is drawing.
+    pub fn show(&self, graphics: &mut GlGraphics, transform: Matrix2d) {
+        let corner = |x: f64, y: f64| [tx(transform, x, y), ty(transform, x, y)];
+        let (width, height) = (self.world.width, self.world.height);
+        let corners = [
+            corner(0.0, 0.0),
+            corner(width, 0.0),
+            corner(width, height),
+            corner(0.0, height),
+        ];
+        let conversion = match self.blend_mode {
+            BlendMode::Alpha => Conversion::None,
+            BlendMode::Additive => Conversion::ToColour,
+        };
+
+        let repeats = [1.0, 1.0];
+        self.quad.draw(
+            self.texture,
+            corners,
+            repeats,
+            1.0,
+            conversion,
+            self.exposure,
+        );
+        graphics.clear_program();
+        graphics.clear_draw_state();
+    }
+
+    // Draws the background over the whole canvas with alpha
+    fn cover(&self, graphics: &mut GlGraphics, alpha: f32) {
+        let corners = [[-1.0, -1.0], [1.0, -1.0], [1.0, 1.0], [-1.0, 1.0]];
+        let conversion = match self.blend_mode {
+            BlendMode::Alpha => Conversion::None,
+            BlendMode::Additive => Conversion::ToLight,
+        };
+
+        self.bind();
+        let repeats = self.background_repeats;
+        self.quad.draw(
+            self.background,
+            corners,
+            repeats,
+            alpha,
+            conversion,
+            self.exposure,
+        );
+        unbind();
+        graphics.clear_program();
+        graphics.clear_draw_state();
+    }
+
+    fn bind(&self) {
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.framebuffer);
+            gl::Viewport(0, 0, self.width as i32, self.height as i32);
+        }
+    }
+}
+
+fn unbind() {
+    unsafe {
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+    }
+}
+
+impl Drop for Canvas {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteFramebuffers(1, &self.framebuffer);
+            gl::DeleteTextures(1, &self.texture);
+            gl::DeleteTextures(1, &self.background);
+        }
+    }
+}
+
+// The widest texture the graphics card can hold, which is at least 16384 on most cards
+pub fn max_texture_side() -> f64 {
+    let mut side = 0;
+    unsafe {
+        gl::GetIntegerv(gl::MAX_TEXTURE_SIZE, &mut side);
+    }
+    side as f64
+}
+
+unsafe fn create_texture(
+    internal_format: GLenum,
+    width: u32,
+    height: u32,
+    wrap: GLenum,
+    pixel_type: GLenum,
+    pixels: *const std::ffi::c_void,
+) -> GLuint {
+    let mut texture = 0;
+    gl::GenTextures(1, &mut texture);
+    gl::BindTexture(gl::TEXTURE_2D, texture);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as GLint);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, wrap as GLint);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, wrap as GLint);
+    gl::TexImage2D(
+        gl::TEXTURE_2D,
+        0,
+        internal_format as GLint,
+        width as i32,
+        height as i32,
+        0,
+        gl::RGBA,
+        pixel_type,
+        pixels,
+    );
+    texture
+}
+
+// The shader program that draws a texture over a quad
+struct Quad {
+    program: GLuint,
+    vao: GLuint,
+    pos: DynamicAttribute,
+    uv: DynamicAttribute,
+    alpha: GLint,
+    conversion: GLint,
+    exposure: GLint,
+}
+
+impl Quad {
+    fn new() -> Self {
+        let program = link_program(QUAD_VERTEX_SHADER, QUAD_FRAGMENT_SHADER);
+        let mut vao = 0;
+        unsafe {
+            gl::GenVertexArrays(1, &mut vao);
+        }
+
+        let uniform = |name| uniform_location(program, name).unwrap() as GLint;
+        Quad {
+            program,
+            vao,
+            pos: DynamicAttribute::xy(program, "pos", vao).unwrap(),
+            uv: DynamicAttribute::uv(program, "uv", vao).unwrap(),
+            alpha: uniform("alpha"),
+            conversion: uniform("conversion"),
+            exposure: uniform("exposure"),
+        }
+    }
+
+    // Draws the texture over the quad with these corners in normalised device coordinates,
+    // starting with the corner at the start of the texture's first row. The texture is
+    // repeated across and down the quad as many times as repeats says.
+    fn draw(
+        &self,
+        texture: GLuint,
+        corners: [[f32; 2]; 4],
+        repeats: [f32; 2],
+        alpha: f32,
+        conversion: Conversion,
+        exposure: f32,
+    ) {
+        let positions = [
+            corners[0], corners[1], corners[2], corners[0], corners[2], corners[3],
+        ];
+        let [u, v] = repeats;
+        let uvs = [[0.0, 0.0], [u, 0.0], [u, v], [0.0, 0.0], [u, v], [0.0, v]];
+
+        unsafe {
+            gl::UseProgram(self.program);
+            gl::BindVertexArray(self.vao);
+            gl::BindTexture(gl::TEXTURE_2D, texture);
+            gl::Uniform1f(self.alpha, alpha);
+            gl::Uniform1i(self.conversion, conversion as GLint);
+            gl::Uniform1f(self.exposure, exposure);
+
+            // the alpha of the canvas is never used so it's left alone
+            if alpha < 1.0 {
+                gl::Enable(gl::BLEND);
+                gl::BlendEquation(gl::FUNC_ADD);
+                gl::BlendFuncSeparate(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA, gl::ZERO, gl::ONE);
+            } else {
+                gl::Disable(gl::BLEND);
+            }
+
+            gl::Disable(gl::CULL_FACE);
+            self.pos.set(&positions);
+            self.uv.set(&uvs);
+            gl::DrawArrays(gl::TRIANGLES, 0, positions.len() as i32);
+            gl::BindVertexArray(0);
+        }
+    }
+}
+
+impl Drop for Quad {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteVertexArrays(1, &self.vao);
+            gl::DeleteProgram(self.program);
+        }
+    }
+}
+
+pub fn link_program(vertex_shader: &str, fragment_shader: &str) -> GLuint {
+    let vertex_shader = compile_shader(gl::VERTEX_SHADER, vertex_shader).unwrap();
+    let fragment_shader = compile_shader(gl::FRAGMENT_SHADER, fragment_shader).unwrap();
+    let output = CString::new("o_colour").unwrap();
+
+    unsafe {
+        let program = gl::CreateProgram();
+        gl::AttachShader(program, vertex_shader);
+        gl::AttachShader(program, fragment_shader);
+        gl::BindFragDataLocation(program, 0, output.as_ptr());
+        gl::LinkProgram(program);
+        gl::DeleteShader(vertex_shader);
+        gl::DeleteShader(fragment_shader);
+
+        let mut linked = gl::FALSE as GLint;
+        gl::GetProgramiv(program, gl::LINK_STATUS, &mut linked);
+        assert_eq!(linked, gl::TRUE as GLint, "the canvas shaders didn't link");
+        program
+    }
+}
+
+// How much has been drawn in each cell of a grid over the plane. It fades along with the
+// canvas and is cleared with it so it's a rough copy of the canvas that's cheap to search.
+struct Density {
+    cells: Vec<f64>,
+}
+
+impl Density {
+    fn new() -> Self {
+        Density {
+            cells: vec![0.0; DENSITY_CELLS * DENSITY_CELLS],
+        }
+    }
+
+    fn clear(&mut self) {
+        self.cells.iter_mut().for_each(|cell| *cell = 0.0);
+    }
+
+    fn fade(&mut self, alpha: f32) {
+        let kept = 1.0 - alpha as f64;
+        self.cells.iter_mut().for_each(|cell| *cell *= kept);
+    }
+
+    // Every triangle counts towards the cell its middle is in, more when it's more opaque
+    fn add(&mut self, batch: &SegmentBatch, world: Size) {
+        let cell = |position: f64, size: f64| {
+            ((position / size * DENSITY_CELLS as f64) as usize).min(DENSITY_CELLS - 1)
+        };
+
+        for (centre, amount) in batch.coverage() {
+            let x = cell(centre[0].max(0.0), world.width);
+            let y = cell(centre[1].max(0.0), world.height);
+            self.cells[y * DENSITY_CELLS + x] += amount;
+        }
+    }
+
+    fn least_dense_point(&self, rng: &mut ThreadRng) -> [f64; 2] {
+        // an empty canvas has lots of equally empty cells so pick any of them
+        let least = self.cells.iter().copied().fold(f64::INFINITY, f64::min);
+        let emptiest: Vec<usize> = (0..self.cells.len())
+            .filter(|&cell| self.cells[cell] == least)
+            .collect();
+        let cell = *emptiest.choose(rng).unwrap();
+
+        let cells = DENSITY_CELLS as f64;
+        [
+            ((cell % DENSITY_CELLS) as f64 + rng.gen::<f64>()) / cells,
+            ((cell / DENSITY_CELLS) as f64 + rng.gen::<f64>()) / cells,
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Segment;
+    use super::*;
+
+    const WORLD: Size = Size {
+        width: 120.0,
+        height: 60.0,
+    };
+
+    // a short line across the middle of the top left cell
+    fn segment() -> Segment {
+        Segment {
+            from: [2.0, 2.5, 0.0],
+            to: [8.0, 2.5, 0.0],
+            colour: [0.5, 0.5, 0.5, 1.0],
+            width: 1.0,
+        }
+    }
+
+    fn density(segments: &[Segment]) -> Density {
+        let mut batch = SegmentBatch::new();
+        for segment in segments {
+            batch.push(segment);
+        }
+
+        let mut density = Density::new();
+        density.add(&batch, WORLD);
+        density
+    }
+
+    #[test]
+    fn the_least_dense_point_avoids_drawing() {
+        let density = density(&[segment()]);
+        let mut rng = rand::thread_rng();
+        for _ in 0..1000 {
+            let point = density.least_dense_point(&mut rng);
+            let cells = DENSITY_CELLS as f64;
+            assert!(point[0] >= 1.0 / cells || point[1] >= 1.0 / cells);
+        }
+    }
+
+    #[test]
+    fn overlapping_segments_add_up_and_fade() {
+        let one = density(&[segment()]).cells[0];
+        let mut two = density(&[segment(), segment()]);
+        assert!(one > 0.0);
+        assert!((two.cells[0] - 2.0 * one).abs() < 1e-9);
+
+        two.fade(0.5);
+        assert!((two.cells[0] - one).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,238 @@
+use rand::prelude::*;
+use serde::Deserialize;
+
+use graphics::color;
+
+// A handful of hand picked palettes that look good on a black background
+const SUNSET: [&str; 5] = ["ffb347", "ff7f50", "ff5e78", "c86dd7", "7b6cf6"];
+const OCEAN: [&str; 5] = ["a8e6ff", "5ac8fa", "2f9bd6", "47d1c3", "9ff5d8"];
+const FOREST: [&str; 5] = ["d4e09b", "a7c957", "6a994e", "e9c46a", "f4a261"];
+const NEON: [&str; 5] = ["ff2fd1", "00f0ff", "b6ff00", "ffe600", "ff6b00"];
+const EMBER: [&str; 5] = ["fff1a8", "ffc24b", "ff8a2b", "ff4d2e", "d1274b"];
+
+const NAMED_PALETTES: [NamedPalette; 5] = [
+    NamedPalette::Sunset,
+    NamedPalette::Ocean,
+    NamedPalette::Forest,
+    NamedPalette::Neon,
+    NamedPalette::Ember,
+];
+
+const HARMONIES: [Harmony; 3] = [Harmony::Analogous, Harmony::Complementary, Harmony::Triadic];
+
+#[derive(Copy, Clone, Debug, Deserialize)]
+pub enum NamedPalette {
+    Sunset,
+    Ocean,
+    Forest,
+    Neon,
+    Ember,
+}
+
+// Hue relationships used to build a palette around a random base hue
+#[derive(Copy, Clone, Debug, Deserialize)]
+pub enum Harmony {
+    Analogous,     // neighbouring hues, +-30 degrees
+    Complementary, // opposite hues with a lighter and darker variant of each
+    Triadic,       // three hues evenly spaced around the wheel
+}
+
+#[derive(Copy, Clone, Debug, Deserialize)]
+pub enum PaletteChoice {
+    Pastel, // every channel uniformly in 0.5..1.0 (the original look)
+    Named(NamedPalette),
+    Hsv(Harmony),
+    Oklch(Harmony),
+    Any, // pick one of the above at random for every system
+}
+
+// How a segment's colour is picked from the system's palette
+#[derive(Copy, Clone, Debug, Deserialize)]
+pub enum ColourMode {
+    Solid,      // one colour from the palette for the whole system
+    Path,       // cycle through the palette as the pen travels
+    Depth,      // step through the palette with bracket depth
+    Progress,   // sweep once through the palette from the start to the end of the string
+    Heading,    // colour wheel by the direction the pen is facing
+    Generation, // colour by the growth cycle that produced each F
+}
+
+#[derive(Clone, Debug)]
+pub struct Palette {
+    colours: Vec<[f32; 4]>,
+}
+
+impl Palette {
+    pub fn new(choice: PaletteChoice, rng: &mut ThreadRng) -> Self {
+        let colours = match choice {
+            PaletteChoice::Pastel => (0..5).map(|_| rand_pastel(rng)).collect(),
+            PaletteChoice::Named(named) => named_colours(named),
+            PaletteChoice::Hsv(harmony) => {
+                let hue = rng.gen_range(0.0, 360.0);
+                harmony_hues(harmony, hue)
+                    .iter()
+                    .map(|&(hue, lighten)| hsv_to_rgb(hue, 0.65 - lighten * 0.3, 0.85 + lighten))
+                    .collect()
+            }
+            PaletteChoice::Oklch(harmony) => {
+                let hue = rng.gen_range(0.0, 360.0);
+                harmony_hues(harmony, hue)
+                    .iter()
+                    .map(|&(hue, lighten)| oklch_to_rgb(0.75 + lighten, 0.13, hue))
+                    .collect()
+            }
+            PaletteChoice::Any => {
+                let choice = match rng.gen_range(0, 4) {
+                    0 => PaletteChoice::Pastel,
+                    1 => PaletteChoice::Named(*NAMED_PALETTES.choose(rng).unwrap()),
+                    2 => PaletteChoice::Hsv(*HARMONIES.choose(rng).unwrap()),
+                    _ => PaletteChoice::Oklch(*HARMONIES.choose(rng).unwrap()),
+                };
+
+                return Palette::new(choice, rng);
+            }
+        };
+
+        Palette { colours }
+    }
+
+    pub fn pick(&self, rng: &mut ThreadRng) -> [f32; 4] {
+        *self.colours.choose(rng).unwrap()
+    }
+
+    // Samples a cyclic gradient through every colour in the palette. The gradient wraps
+    // around so t = 0.0 and t = 1.0 give the same colour and there are no sudden jumps.
+    // Colours are blended in OKLab so the midpoints don't go grey.
+    pub fn sample(&self, t: f64) -> [f32; 4] {
+        let len = self.colours.len();
+        let t = t.rem_euclid(1.0) * len as f64;
+        let index = t.floor() as usize % len;
+        let from = srgb_to_oklab(self.colours[index]);
+        let to = srgb_to_oklab(self.colours[(index + 1) % len]);
+        let frac = t.fract();
+
+        oklab_to_srgb([
+            from[0] + (to[0] - from[0]) * frac,
+            from[1] + (to[1] - from[1]) * frac,
+            from[2] + (to[2] - from[2]) * frac,
+        ])
+    }
+}
+
+fn rand_pastel(rng: &mut ThreadRng) -> [f32; 4] {
+    let red = rng.gen_range(0.5, 1.0);
+    let green = rng.gen_range(0.5, 1.0);
+    let blue = rng.gen_range(0.5, 1.0);
+    [red, green, blue, 1.0]
+}
+
+fn named_colours(named: NamedPalette) -> Vec<[f32; 4]> {
+    let hexes = match named {
+        NamedPalette::Sunset => SUNSET,
+        NamedPalette::Ocean => OCEAN,
+        NamedPalette::Forest => FOREST,
+        NamedPalette::Neon => NEON,
+        NamedPalette::Ember => EMBER,
+    };
+
+    hexes.iter().map(|hex| color::hex(hex)).collect()
+}
+
+// returns (hue in degrees, lightness offset) pairs
+fn harmony_hues(harmony: Harmony, hue: f64) -> Vec<(f64, f64)> {
+    match harmony {
+        Harmony::Analogous => vec![
+            (hue - 30.0, 0.0),
+            (hue - 15.0, 0.05),
+            (hue, 0.1),
+            (hue + 15.0, 0.05),
+            (hue + 30.0, 0.0),
+        ],
+        Harmony::Complementary => vec![
+            (hue, 0.0),
+            (hue, 0.1),
+            (hue + 180.0, 0.0),
+            (hue + 180.0, 0.1),
+        ],
+        Harmony::Triadic => vec![(hue, 0.0), (hue + 120.0, 0.0), (hue + 240.0, 0.0)],
+    }
+}
+
+// hue in degrees, saturation and value in 0..1
+pub fn hsv_to_rgb(hue: f64, saturation: f64, value: f64) -> [f32; 4] {
+    let saturation = saturation.clamp(0.0, 1.0);
+    let value = value.clamp(0.0, 1.0);
+    let hue = hue.rem_euclid(360.0) / 60.0;
+    let chroma = value * saturation;
+    let x = chroma * (1.0 - (hue % 2.0 - 1.0).abs());
+    let (r, g, b) = match hue as usize {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+
+    let m = value - chroma;
+    [(r + m) as f32, (g + m) as f32, (b + m) as f32, 1.0]
+}
+
+// lightness in 0..1, chroma roughly in 0..0.37, hue in degrees
+pub fn oklch_to_rgb(lightness: f64, chroma: f64, hue: f64) -> [f32; 4] {
+    let hue = hue.to_radians();
+    oklab_to_srgb([lightness, chroma * hue.cos(), chroma * hue.sin()])
+}
+
+// See https://bottosson.github.io/posts/oklab/ for the matrices below
+fn srgb_to_oklab(colour: [f32; 4]) -> [f64; 3] {
+    let r = srgb_to_linear(colour[0] as f64);
+    let g = srgb_to_linear(colour[1] as f64);
+    let b = srgb_to_linear(colour[2] as f64);
+
+    let l = (0.4122214708 * r + 0.5363325363 * g + 0.0514459929 * b).cbrt();
+    let m = (0.2119034982 * r + 0.6806995451 * g + 0.1073969566 * b).cbrt();
+    let s = (0.0883024619 * r + 0.2817188376 * g + 0.6299787005 * b).cbrt();
+
+    [
+        0.2104542553 * l + 0.7936177850 * m - 0.0040720468 * s,
+        1.9779984951 * l - 2.4285922050 * m + 0.4505937099 * s,
+        0.0259040371 * l + 0.7827717662 * m - 0.8086757660 * s,
+    ]
+}
+
+fn oklab_to_srgb(lab: [f64; 3]) -> [f32; 4] {
+    let l = lab[0] + 0.3963377774 * lab[1] + 0.2158037573 * lab[2];
+    let m = lab[0] - 0.1055613458 * lab[1] - 0.0638541728 * lab[2];
+    let s = lab[0] - 0.0894841775 * lab[1] - 1.2914855480 * lab[2];
+    let (l, m, s) = (l * l * l, m * m * m, s * s * s);
+
+    let r = 4.0767416621 * l - 3.3077115913 * m + 0.2309699292 * s;
+    let g = -1.2684380046 * l + 2.6097574011 * m - 0.3413193965 * s;
+    let b = -0.0041960863 * l - 0.7034186147 * m + 1.7076147010 * s;
+
+    [
+        linear_to_srgb(r) as f32,
+        linear_to_srgb(g) as f32,
+        linear_to_srgb(b) as f32,
+        1.0,
+    ]
+}
+
+pub fn srgb_to_linear(c: f64) -> f64 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+// clamps to the displayable range so out of gamut colours are simply clipped
+pub fn linear_to_srgb(c: f64) -> f64 {
+    let c = c.clamp(0.0, 1.0);
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
@@ -0,0 +1,336 @@
+use notify_debouncer_mini::notify::{RecommendedWatcher, RecursiveMode};
+use notify_debouncer_mini::{new_debouncer, DebounceEventResult, Debouncer};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver};
+use std::time::Duration;
+
+use super::*;
+
+// How long the file has to be left alone before it's read again, editors often save a file
+// in more than one write
+const RELOAD_DELAY: Duration = Duration::from_millis(500);
+
+// The settings file, config.toml in an lsaver folder in the platform config directory
+// (%APPDATA% on Windows, ~/.config on Linux). Every setting is optional and falls back to
+// the value in Parameters::new, enums are written the way serde does them e.g.
+//
+//     fade_mode = { Age = "Exponential" }
+//     geometry = { Flat = "KleinBottle" }
+//     stroke_style = "Ribbon"
+pub struct Config {
+    path: Option<PathBuf>,
+    watcher: Option<(Debouncer<RecommendedWatcher>, Receiver<DebounceEventResult>)>,
+}
+
+impl Config {
+    pub fn new() -> Self {
+        let path = dirs::config_dir().map(|dir| dir.join("lsaver").join("config.toml"));
+        let watcher = path.as_deref().and_then(watch);
+        Config { path, watcher }
+    }
+
+    // The settings to start with, the defaults if there is no file or it has a problem
+    pub fn load(&self) -> Parameters {
+        match self.read() {
+            Ok(params) => params,
+            Err(error) => {
+                eprintln!("{}, using the defaults", error);
+                Parameters::new()
+            }
+        }
+    }
+
+    // The new settings if the file has changed since the last call. A file with a problem
+    // is ignored so the saver carries on as it was until it's fixed.
+    pub fn changed(&mut self) -> Option<Parameters> {
+        let (path, (debouncer, events)) = (self.path.as_ref()?, self.watcher.as_mut()?);
+        let folder = path.parent()?;
+        let mut touched = false;
+        for event in events.try_iter().flatten().flatten() {
+            if event.path == folder && folder.is_dir() {
+                // the folder has only just been made, from now on watch inside it as well
+                let _ = debouncer
+                    .watcher()
+                    .watch(folder, RecursiveMode::NonRecursive);
+                touched |= path.exists();
+            }
+
+            touched |= event.path == *path;
+        }
+
+        if !touched {
+            return None;
+        }
+
+        match self.read() {
+            Ok(params) => Some(params),
+            Err(error) => {
+                eprintln!("{}, keeping the current settings", error);
+                None
+            }
+        }
+    }
+
+    fn read(&self) -> Result<Parameters, String> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(Parameters::new()),
+        };
+
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(Parameters::new()),
+            Err(error) => return Err(format!("{}: {}", path.display(), error)),
+        };
+
+        let params: Parameters =
+            toml::from_str(&text).map_err(|error| format!("{}: {}", path.display(), error))?;
+        params
+            .validate()
+            .map_err(|error| format!("{}: {}", path.display(), error))?;
+        Ok(params)
+    }
+}
+
+impl Default for Parameters {
+    fn default() -> Self {
+        Parameters::new()
+    }
+}
+
+impl Parameters {
+    // Catches settings that would make no sense or crash the saver, such as a min above
+    // its max
+    pub fn validate(&self) -> Result<(), String> {
+        let mut problems = Vec::new();
+        let mut check = |ok: bool, problem: &str| {
+            if !ok {
+                problems.push(problem.to_string());
+            }
+        };
+
+        // an infinity gets past every comparison below that a NaN fails
+        let numbers = [
+            self.random_angle_chance,
+            self.min_branch_width_ratio,
+            self.max_branch_width_ratio,
+            self.min_branch_length_ratio,
+            self.max_branch_length_ratio,
+            self.turn_jitter,
+            self.length_jitter,
+            self.virtual_scale,
+            self.distance_per_movement,
+            self.line_width,
+            self.min_pen_speed,
+            self.max_pen_speed,
+            self.seconds_between_spawns,
+            self.seconds_per_fade,
+            self.segment_lifetime,
+            self.segment_hold,
+            self.light_exposure as f64,
+            self.spin,
+            self.tropism[0],
+            self.tropism[1],
+            self.tropism[2],
+            self.tropism_susceptibility,
+            self.gradient_length,
+        ];
+        check(
+            numbers.iter().all(|number| number.is_finite()),
+            "every number must be finite",
+        );
+        let background_ok = match self.background {
+            Background::Solid(colour) | Background::Noise(colour) => {
+                colour.iter().all(|c| c.is_finite())
+            }
+            Background::VerticalGradient(top, bottom) => {
+                top.iter().chain(bottom.iter()).all(|c| c.is_finite())
+            }
+            Background::ShiftingGradient(seconds_per_turn) => {
+                seconds_per_turn.is_finite() && seconds_per_turn > 0.0
+            }
+        };
+        check(
+            background_ok,
+            "background colours must be finite and a ShiftingGradient must take over 0 seconds",
+        );
+
+        check(self.min_rules >= 1, "min_rules must be at least 1");
+        check(
+            self.min_rules < self.max_rules,
+            "min_rules must be less than max_rules",
+        );
+        check(
+            self.min_start_length >= 1,
+            "min_start_length must be at least 1",
+        );
+        check(
+            self.min_start_length < self.max_start_length,
+            "min_start_length must be less than max_start_length",
+        );
+        check(
+            self.min_rule_length >= 1,
+            "min_rule_length must be at least 1",
+        );
+        check(
+            self.min_rule_length < self.max_rule_length,
+            "min_rule_length must be less than max_rule_length",
+        );
+        check(
+            self.lsystem_max_length >= 1,
+            "lsystem_max_length must be at least 1",
+        );
+        check(
+            (0.0..=1.0).contains(&self.random_angle_chance),
+            "random_angle_chance must be between 0 and 1",
+        );
+        check(
+            0.0 < self.min_branch_width_ratio
+                && self.min_branch_width_ratio < self.max_branch_width_ratio,
+            "min_branch_width_ratio must be above 0 and less than max_branch_width_ratio",
+        );
+        check(
+            0.0 < self.min_branch_length_ratio
+                && self.min_branch_length_ratio < self.max_branch_length_ratio,
+            "min_branch_length_ratio must be above 0 and less than max_branch_length_ratio",
+        );
+        check(
+            self.turn_jitter >= 0.0 && self.length_jitter >= 0.0,
+            "turn_jitter and length_jitter can't be negative",
+        );
+
+        check(
+            (1.0..=MAX_VIRTUAL_SCALE).contains(&self.virtual_scale),
+            &format!("virtual_scale must be between 1 and {}", MAX_VIRTUAL_SCALE),
+        );
+        check(
+            self.distance_per_movement > 0.0,
+            "distance_per_movement must be above 0",
+        );
+        check(self.line_width > 0.0, "line_width must be above 0");
+        check(
+            0.0 < self.min_pen_speed && self.min_pen_speed < self.max_pen_speed,
+            "min_pen_speed must be above 0 and less than max_pen_speed",
+        );
+        check(self.max_turtles >= 1, "max_turtles must be at least 1");
+        check(
+            self.seconds_between_spawns >= 0.0,
+            "seconds_between_spawns can't be negative",
+        );
+        check(
+            self.max_growth_fronts >= 1,
+            "max_growth_fronts must be at least 1",
+        );
+        check(
+            self.seconds_per_fade > 0.0,
+            "seconds_per_fade must be above 0",
+        );
+        check(
+            self.segment_lifetime > 0.0,
+            "segment_lifetime must be above 0",
+        );
+        check(
+            (0.0..1.0).contains(&self.segment_hold),
+            "segment_hold must be at least 0 and less than 1",
+        );
+        check(
+            (1..=MAX_HISTORY_SEGMENTS).contains(&self.max_history_segments),
+            &format!(
+                "max_history_segments must be between 1 and {}",
+                MAX_HISTORY_SEGMENTS
+            ),
+        );
+        check(self.light_exposure > 0.0, "light_exposure must be above 0");
+        check(
+            self.tropism_susceptibility >= 0.0,
+            "tropism_susceptibility can't be negative",
+        );
+
+        check(
+            self.gradient_length > 0.0,
+            "gradient_length must be above 0",
+        );
+        check(
+            self.gradient_depth >= 1,
+            "gradient_depth must be at least 1",
+        );
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems.join(", "))
+        }
+    }
+}
+
+// Watches the folder rather than the file so it still works when the file is only created
+// later or an editor saves by replacing it. Until the folder itself is made its parent is
+// watched instead, nothing is created just to have something to watch.
+fn watch(path: &Path) -> Option<(Debouncer<RecommendedWatcher>, Receiver<DebounceEventResult>)> {
+    let folder = path.parent()?;
+    let watched = if folder.is_dir() {
+        folder
+    } else {
+        folder.parent()?
+    };
+
+    let (sender, events) = channel();
+    let mut debouncer = new_debouncer(RELOAD_DELAY, sender).ok()?;
+    debouncer
+        .watcher()
+        .watch(watched, RecursiveMode::NonRecursive)
+        .ok()?;
+    Some((debouncer, events))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Result<Parameters, String> {
+        let params: Parameters = toml::from_str(text).map_err(|error| error.to_string())?;
+        params.validate()?;
+        Ok(params)
+    }
+
+    #[test]
+    fn readme_example_is_valid() {
+        let readme = include_str!("../README.md");
+        let start = readme
+            .find("```toml\n")
+            .expect("no toml example in the README")
+            + 8;
+        let length = readme[start..].find("```").unwrap();
+        let params = parse(&readme[start..start + length]).unwrap();
+        assert_eq!(params.max_turtles, 5);
+        assert!(matches!(
+            params.geometry,
+            Geometry::Flat(Topology::KleinBottle)
+        ));
+    }
+
+    #[test]
+    fn empty_file_gives_the_defaults() {
+        let params = parse("").unwrap();
+        let defaults = Parameters::new();
+        assert_eq!(params.max_turtles, defaults.max_turtles);
+        assert_eq!(params.lsystem_max_length, defaults.lsystem_max_length);
+        assert_eq!(params.line_width, defaults.line_width);
+        assert_eq!(params.virtual_scale, defaults.virtual_scale);
+    }
+
+    #[test]
+    fn unknown_settings_are_rejected() {
+        assert!(parse("max_turtle = 5").is_err());
+    }
+
+    #[test]
+    fn min_at_or_above_max_is_rejected() {
+        assert!(parse("min_rules = 5\nmax_rules = 2").is_err());
+        assert!(parse("min_pen_speed = 100.0\nmax_pen_speed = 100.0").is_err());
+        assert!(parse("segment_hold = 1.0").is_err());
+        assert!(parse("spin = inf").is_err());
+    }
+}
@@ -0,0 +1,421 @@
+use serde::Deserialize;
+use std::f64::consts::PI;
+
+use super::*;
+
+// How far in pixels the pen moves before a curved line gets a new straight segment
+const CURVE_STEP: f64 = 2.0;
+
+// How far the disk and globe reach towards the edges of the screen
+const SCREEN_FILL: f64 = 0.95;
+
+// Keeps the hyperbolic turtle strictly inside the disk where the maths still works
+const MAX_DISK_RADIUS: f64 = 0.999999;
+
+// How far the globe's north pole is tipped towards the viewer in radians
+const GLOBE_TILT: f64 = 0.4;
+
+// The space the turtle moves through. Turning always works the same way but going forward
+// follows the straightest possible line in that space.
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+pub enum Geometry {
+    Flat(Topology), // the screen with its edges joined together
+    // The Poincare disk model of the hyperbolic plane. The turtle's x, y are screen
+    // coordinates inside the disk and lines bend and shrink towards its edge.
+    Hyperbolic,
+    // The surface of a globe that spins slowly in front of the viewer. The turtle's x, y
+    // are a map of the globe with longitude across the screen and latitude down it.
+    Spherical,
+    // Ordinary 3D space seen through a camera that slowly orbits the centre of the screen.
+    // The turtle can also pitch and roll and starts off lying flat in the screen.
+    Space,
+}
+
+// How the edges of the flat screen are joined together. When the pen goes off one edge it
+// comes back on at the opposite edge, possibly mirrored.
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+pub enum Topology {
+    Torus,           // every edge joined straight across
+    KleinBottle,     // the left and right edges are joined with a twist
+    MobiusStrip,     // the left and right edges are joined with a twist, top and bottom are walls
+    ProjectivePlane, // every edge joined with a twist
+}
+
+// How a pair of opposite edges are joined
+pub enum Identification {
+    Straight, // come back at the same place on the opposite edge
+    Twisted,  // come back on the opposite edge mirrored along it, heading mirrored too
+    Wall,     // bounce off the edge like a mirror
+}
+
+impl Topology {
+    // returns how the left and right edges and the top and bottom edges are joined
+    pub fn identifications(self) -> (Identification, Identification) {
+        use Identification::*;
+
+        match self {
+            Topology::Torus => (Straight, Straight),
+            Topology::KleinBottle => (Twisted, Straight),
+            Topology::MobiusStrip => (Twisted, Wall),
+            Topology::ProjectivePlane => (Twisted, Twisted),
+        }
+    }
+}
+
+// Moves the pen along a hyperbolic line (a circular arc that meets the edge of the disk at
+// right angles) and returns where it ends up and which way it's facing
+#[allow(clippy::too_many_arguments)]
+pub fn hyperbolic_forward(
+    x: f64,
+    y: f64,
+    angle: f64,
+    distance: f64,
+    width: f64,
+    colour: [f32; 4],
+    screen: Size,
+    segments: &mut Vec<Segment>,
+) -> (f64, f64, f64) {
+    let radius = disk_radius(screen);
+    let centre = [screen.width / 2.0, screen.height / 2.0];
+    let to_screen = |z: [f64; 2]| [centre[0] + z[0] * radius, centre[1] + z[1] * radius, 0.0];
+
+    let mut z = into_disk([(x - centre[0]) / radius, (y - centre[1]) / radius]);
+    let mut heading = angle;
+    let steps = (distance / CURVE_STEP).ceil().max(1.0);
+
+    // a small step at the centre of the disk covers twice its length on the screen
+    let step = 2.0 * distance / radius / steps;
+    for _ in 0..steps as usize {
+        let (next_z, next_heading) = hyperbolic_step(z, heading, step);
+
+        // the disk shrinks everything towards its edge and that includes the pen
+        let scale = 1.0 - (z[0] * z[0] + z[1] * z[1]);
+        segments.push(Segment {
+            from: to_screen(z),
+            to: to_screen(next_z),
+            colour,
+            width: width * scale,
+        });
+
+        z = next_z;
+        heading = next_heading;
+    }
+
+    let end = to_screen(z);
+    (end[0], end[1], heading)
+}
+
+// Moves distance along the line through z with the given heading. The disk is shifted so
+// z is at the centre where lines are straight and then shifted back again. The disk is
+// conformal so the heading is only changed by how much the shift back rotates things.
+fn hyperbolic_step(z: [f64; 2], heading: f64, distance: f64) -> ([f64; 2], f64) {
+    let length = (distance / 2.0).tanh();
+    let p = [length * heading.cos(), length * heading.sin()];
+
+    // (p + z) / (1 + conj(z) * p)
+    let denominator = [1.0 + z[0] * p[0] + z[1] * p[1], z[0] * p[1] - z[1] * p[0]];
+    let moved = into_disk(divide([p[0] + z[0], p[1] + z[1]], denominator));
+    let rotation = -2.0 * denominator[1].atan2(denominator[0]);
+
+    (moved, heading + rotation)
+}
+
+fn divide(a: [f64; 2], b: [f64; 2]) -> [f64; 2] {
+    let norm = b[0] * b[0] + b[1] * b[1];
+    [
+        (a[0] * b[0] + a[1] * b[1]) / norm,
+        (a[1] * b[0] - a[0] * b[1]) / norm,
+    ]
+}
+
+// Points outside the disk are reflected inside it through its edge
+fn into_disk(z: [f64; 2]) -> [f64; 2] {
+    let mut norm = z[0] * z[0] + z[1] * z[1];
+    let mut z = z;
+    if norm > 1.0 {
+        z = [z[0] / norm, z[1] / norm];
+        norm = 1.0 / norm;
+    }
+
+    let length = norm.sqrt();
+    if length > MAX_DISK_RADIUS {
+        [
+            z[0] * MAX_DISK_RADIUS / length,
+            z[1] * MAX_DISK_RADIUS / length,
+        ]
+    } else {
+        z
+    }
+}
+
+fn disk_radius(screen: Size) -> f64 {
+    screen.width.min(screen.height) / 2.0 * SCREEN_FILL
+}
+
+// Moves the pen along a great circle of the globe and returns where it ends up on the map
+// and which way it's facing. Segments are drawn on the map and only put on the globe when
+// they are projected onto the screen.
+#[allow(clippy::too_many_arguments)]
+pub fn spherical_forward(
+    x: f64,
+    y: f64,
+    angle: f64,
+    distance: f64,
+    width: f64,
+    colour: [f32; 4],
+    screen: Size,
+    segments: &mut Vec<Segment>,
+) -> (f64, f64, f64) {
+    let (mut point, mut heading) = to_globe(x, y, angle, screen);
+    let steps = (distance / CURVE_STEP).ceil().max(1.0);
+    let step = distance / disk_radius(screen) / steps;
+    let (sin, cos) = step.sin_cos();
+    let mut from = [x, y, 0.0];
+
+    for _ in 0..steps as usize {
+        // rotate the point and heading together in the plane of the great circle
+        let next_point = normalise(add(scale(point, cos), scale(heading, sin)));
+        heading = normalise(add(scale(heading, cos), scale(point, -sin)));
+        point = next_point;
+
+        let (to_x, to_y, _) = from_globe(point, heading, screen);
+        segments.push(Segment {
+            from,
+            to: [to_x, to_y, 0.0],
+            colour,
+            width,
+        });
+        from = [to_x, to_y, 0.0];
+    }
+
+    from_globe(point, heading, screen)
+}
+
+// Map coordinates to a point on the unit sphere and a unit heading tangent to it. An
+// angle of 0 faces east and angles increase towards the south just like on the screen.
+fn to_globe(x: f64, y: f64, angle: f64, screen: Size) -> ([f64; 3], [f64; 3]) {
+    let longitude = x / screen.width * 2.0 * PI;
+    let latitude = (0.5 - y / screen.height) * PI;
+    let (east, north) = local_axes(longitude, latitude);
+    let point = [
+        latitude.cos() * longitude.cos(),
+        latitude.cos() * longitude.sin(),
+        latitude.sin(),
+    ];
+    let heading = add(scale(east, angle.cos()), scale(north, -angle.sin()));
+
+    (point, heading)
+}
+
+fn from_globe(point: [f64; 3], heading: [f64; 3], screen: Size) -> (f64, f64, f64) {
+    let longitude = point[1].atan2(point[0]).rem_euclid(2.0 * PI);
+    let latitude = point[2].clamp(-1.0, 1.0).asin();
+    let (east, north) = local_axes(longitude, latitude);
+    let angle = (-dot(heading, north)).atan2(dot(heading, east));
+
+    (
+        longitude / (2.0 * PI) * screen.width,
+        (0.5 - latitude / PI) * screen.height,
+        angle,
+    )
+}
+
+fn local_axes(longitude: f64, latitude: f64) -> ([f64; 3], [f64; 3]) {
+    let east = [-longitude.sin(), longitude.cos(), 0.0];
+    let north = [
+        -latitude.sin() * longitude.cos(),
+        -latitude.sin() * longitude.sin(),
+        latitude.cos(),
+    ];
+
+    (east, north)
+}
+
+// Puts segments drawn on the map of the globe onto the screen with the globe turned by
+// rotation radians. Segments on the far side of the globe are hidden. Space is seen by a
+// camera that has orbited by rotation radians and other geometries are drawn on the
+// screen already.
+pub fn project(drawing: Drawing, geometry: Geometry, screen: Size, rotation: f64) -> Drawing {
+    match geometry {
+        Geometry::Flat(_) | Geometry::Hyperbolic => return drawing,
+        Geometry::Space => return project_space(drawing, screen, rotation),
+        Geometry::Spherical => {}
+    }
+
+    let to_screen = |p| globe_to_screen(p, screen, rotation);
+    Drawing {
+        segments: drawing
+            .segments
+            .iter()
+            .filter_map(|segment| {
+                Some(Segment {
+                    from: to_screen(segment.from)?,
+                    to: to_screen(segment.to)?,
+                    ..*segment
+                })
+            })
+            .collect(),
+        polygons: drawing
+            .polygons
+            .iter()
+            .filter_map(|polygon| {
+                let points: Option<Vec<_>> = polygon.points.iter().map(|&p| to_screen(p)).collect();
+                Some(polygon.with_points(points?))
+            })
+            .collect(),
+    }
+}
+
+// Where a single point drawn by the turtles ends up on the screen, the same as project
+// puts it. None if it's hidden on the far side of the globe or behind the space camera.
+pub fn project_point(
+    point: [f64; 3],
+    geometry: Geometry,
+    screen: Size,
+    rotation: f64,
+) -> Option<[f64; 3]> {
+    match geometry {
+        Geometry::Flat(_) | Geometry::Hyperbolic => Some(point),
+        Geometry::Space => space_to_screen(point, screen, rotation).map(|(point, _)| point),
+        Geometry::Spherical => globe_to_screen(point, screen, rotation),
+    }
+}
+
+// Returns screen coordinates of a point on the map of the globe or None if the point is on
+// the far side
+fn globe_to_screen(p: [f64; 3], screen: Size, rotation: f64) -> Option<[f64; 3]> {
+    let radius = disk_radius(screen);
+    let centre = [screen.width / 2.0, screen.height / 2.0];
+    let (tilt_sin, tilt_cos) = GLOBE_TILT.sin_cos();
+
+    let longitude = p[0] / screen.width * 2.0 * PI + rotation;
+    let latitude = (0.5 - p[1] / screen.height) * PI;
+    let x = latitude.cos() * longitude.cos();
+    let y = latitude.cos() * longitude.sin();
+    let z = latitude.sin();
+
+    // the viewer looks at the globe from the +y direction, tipped up by the tilt
+    let depth = y * tilt_cos + z * tilt_sin;
+    let up = z * tilt_cos - y * tilt_sin;
+    if depth < 0.0 {
+        None
+    } else {
+        Some([centre[0] - x * radius, centre[1] - up * radius, 0.0])
+    }
+}
+
+pub fn add(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+pub fn scale(a: [f64; 3], s: f64) -> [f64; 3] {
+    [a[0] * s, a[1] * s, a[2] * s]
+}
+
+pub fn cross(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+pub fn dot(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+pub fn normalise(a: [f64; 3]) -> [f64; 3] {
+    scale(a, 1.0 / dot(a, a).sqrt())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCREEN: Size = Size {
+        width: 200.0,
+        height: 100.0,
+    };
+
+    #[test]
+    fn hyperbolic_steps_stay_inside_the_disk() {
+        // heading straight for the edge from close to it, much further than the screen
+        let mut z = [0.9, 0.0];
+        let mut heading = 0.0;
+        for _ in 0..100 {
+            let (next_z, next_heading) = hyperbolic_step(z, heading, 10.0);
+            assert!(next_z[0] * next_z[0] + next_z[1] * next_z[1] < 1.0);
+            z = next_z;
+            heading = next_heading;
+        }
+
+        let radius = disk_radius(SCREEN);
+        let mut segments = Vec::new();
+        hyperbolic_forward(
+            190.0,
+            50.0,
+            0.0,
+            1000.0,
+            1.0,
+            [1.0; 4],
+            SCREEN,
+            &mut segments,
+        );
+        assert!(!segments.is_empty());
+        for segment in segments {
+            let x = segment.to[0] - SCREEN.width / 2.0;
+            let y = segment.to[1] - SCREEN.height / 2.0;
+            assert!((x * x + y * y).sqrt() < radius);
+        }
+    }
+
+    #[test]
+    fn the_far_side_of_the_globe_is_hidden() {
+        // a short line on the equator a quarter of the way round the map faces the viewer
+        // and one three quarters of the way round faces away, their widths tell them apart
+        let line = |x: f64| Segment {
+            from: [x, 50.0, 0.0],
+            to: [x + 1.0, 50.0, 0.0],
+            colour: [1.0; 4],
+            width: x,
+        };
+        let drawing = || Drawing {
+            segments: vec![line(50.0), line(150.0)],
+            polygons: Vec::new(),
+        };
+        let widths = |drawing: Drawing| -> Vec<f64> {
+            drawing
+                .segments
+                .iter()
+                .map(|segment| segment.width)
+                .collect()
+        };
+
+        let projected = project(drawing(), Geometry::Spherical, SCREEN, 0.0);
+        assert_eq!(widths(projected), vec![50.0]);
+
+        // half a turn later it's the other way round
+        let projected = project(drawing(), Geometry::Spherical, SCREEN, PI);
+        assert_eq!(widths(projected), vec![150.0]);
+    }
+
+    #[test]
+    fn a_point_lands_where_its_segment_does() {
+        // the lead turtle is followed where its lines are drawn on the globe and in space
+        let segment = Segment {
+            from: [50.0, 30.0, 20.0],
+            to: [60.0, 40.0, 20.0],
+            colour: [1.0; 4],
+            width: 1.0,
+        };
+        for &geometry in [Geometry::Spherical, Geometry::Space].iter() {
+            let drawing = Drawing {
+                segments: vec![segment],
+                polygons: Vec::new(),
+            };
+            let projected = project(drawing, geometry, SCREEN, 0.5);
+            let point = project_point(segment.from, geometry, SCREEN, 0.5);
+            assert_eq!(point, Some(projected.segments[0].from));
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use std::f64::consts::TAU;
+
+use super::*;
+
+// Outlines in units of the current step length. x is the way the turtle is facing and y
+// is to its left, the turtle is at the origin.
+const LEAF: [[f64; 2]; 6] = [
+    [0.0, 0.0],
+    [0.3, 0.25],
+    [0.7, 0.3],
+    [1.5, 0.0],
+    [0.7, -0.3],
+    [0.3, -0.25],
+];
+const PETAL: [[f64; 2]; 4] = [[0.0, 0.0], [0.25, 0.15], [0.5, 0.0], [0.25, -0.15]];
+const BUD: [[f64; 2]; 6] = [
+    [0.0, 0.0],
+    [0.2, 0.12],
+    [0.4, 0.1],
+    [0.5, 0.0],
+    [0.4, -0.1],
+    [0.2, -0.12],
+];
+
+const FLOWER_PETALS: usize = 5;
+const FLOWER_CENTRE: f64 = 0.5; // how far ahead of the turtle the middle of the flower is
+const FLOWER_CENTRE_RADIUS: f64 = 0.12;
+const FLOWER_CENTRE_CORNERS: usize = 8;
+
+// Predefined shapes stamped at the turtle's position facing the way it's facing
+#[derive(Copy, Clone, Debug)]
+pub enum Glyph {
+    Leaf,
+    Flower,
+    Bud,
+}
+
+impl Glyph {
+    pub fn from_char(ch: char) -> Option<Glyph> {
+        match ch {
+            'L' => Some(Glyph::Leaf),
+            'K' => Some(Glyph::Flower),
+            'O' => Some(Glyph::Bud),
+            _ => None,
+        }
+    }
+
+    // Adds the glyph's polygons to the drawing. size is the length of one step.
+    pub fn stamp(
+        self,
+        pos: &Position,
+        geometry: Geometry,
+        size: f64,
+        colour: [f32; 4],
+        drawing: &mut Drawing,
+    ) {
+        // only the 3D turtle keeps its frame up to date, a flat one just has an angle
+        let frame = match geometry {
+            Geometry::Space => pos.frame,
+            _ => Frame::flat(pos.angle),
+        };
+        let (heading, left) = (frame.heading, frame.left);
+
+        let origin = [pos.x, pos.y, pos.z];
+        let place = |outline: &[[f64; 2]], offset: [f64; 2], angle: f64| -> Vec<[f64; 3]> {
+            let (sin, cos) = angle.sin_cos();
+            outline
+                .iter()
+                .map(|p| {
+                    let u = offset[0] + (p[0] * cos - p[1] * sin);
+                    let v = offset[1] + (p[0] * sin + p[1] * cos);
+                    add(origin, add(scale(heading, u * size), scale(left, v * size)))
+                })
+                .collect()
+        };
+
+        match self {
+            Glyph::Leaf => drawing
+                .polygons
+                .push(Polygon::new(place(&LEAF, [0.0, 0.0], 0.0), colour)),
+            Glyph::Bud => drawing
+                .polygons
+                .push(Polygon::new(place(&BUD, [0.0, 0.0], 0.0), colour)),
+            Glyph::Flower => {
+                for petal in 0..FLOWER_PETALS {
+                    let angle = TAU * petal as f64 / FLOWER_PETALS as f64;
+                    drawing.polygons.push(Polygon::new(
+                        place(&PETAL, [FLOWER_CENTRE, 0.0], angle),
+                        colour,
+                    ));
+                }
+
+                // a paler middle so the flower stands out from its petals
+                let centre: Vec<[f64; 2]> = (0..FLOWER_CENTRE_CORNERS)
+                    .map(|corner| {
+                        let angle = TAU * corner as f64 / FLOWER_CENTRE_CORNERS as f64;
+                        [
+                            FLOWER_CENTRE_RADIUS * angle.cos(),
+                            FLOWER_CENTRE_RADIUS * angle.sin(),
+                        ]
+                    })
+                    .collect();
+                let pale = |c: f32| c + (1.0 - c) * 0.6;
+                drawing.polygons.push(Polygon::new(
+                    place(&centre, [FLOWER_CENTRE, 0.0], 0.0),
+                    [pale(colour[0]), pale(colour[1]), pale(colour[2]), colour[3]],
+                ));
+            }
+        }
+    }
+}
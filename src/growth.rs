@@ -0,0 +1,108 @@
+use serde::Deserialize;
+use std::collections::VecDeque;
+
+use super::*;
+
+#[derive(Copy, Clone, Debug, Deserialize)]
+pub enum DrawingOrder {
+    Sequential,   // follow the string from start to end, one branch after another
+    BreadthFirst, // grow every branch at one bracket depth together before going deeper
+    RoundRobin,   // grow every open branch together, new branches start as soon as they're reached
+}
+
+// A branch of the drawing that grows independently of the others
+pub struct GrowthFront {
+    pub turtle_state: TurtleState,
+    pub current_string: CurrentString,
+}
+
+// All the branches of one lsystem that are currently growing. The pen's distance is shared
+// equally between them so the drawing speed is the same whatever the drawing order.
+pub struct GrowthFronts {
+    active: Vec<GrowthFront>,
+    pending: VecDeque<GrowthFront>, // branches that have been reached but not started yet
+    next_depth: Vec<GrowthFront>,   // BreadthFirst only, branches one bracket deeper than pending
+}
+
+impl GrowthFronts {
+    pub fn new() -> Self {
+        GrowthFronts {
+            active: Vec::new(),
+            pending: VecDeque::new(),
+            next_depth: Vec::new(),
+        }
+    }
+
+    pub fn start(&mut self, front: GrowthFront) {
+        self.pending.push_back(front);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.active.is_empty() && self.pending.is_empty() && self.next_depth.is_empty()
+    }
+
+    pub fn wrap_to(&mut self, screen: Size) {
+        let fronts = self.active.iter_mut();
+        let fronts = fronts.chain(self.pending.iter_mut());
+        let fronts = fronts.chain(self.next_depth.iter_mut());
+        for front in fronts {
+            front.turtle_state.wrap_to(screen);
+        }
+    }
+
+    // Grows the branches until distance is used up or every branch is finished. The turtle
+    // state of the last branch to finish is copied to turtle_state so the next lsystem
+    // carries on from there.
+    pub fn advance(
+        &mut self,
+        distance: &mut f64,
+        turtle_state: &mut TurtleState,
+        params: &Parameters,
+        screen: Size,
+        drawing: &mut Drawing,
+    ) {
+        while *distance > 0.0 && !self.is_empty() {
+            if self.active.is_empty() && self.pending.is_empty() {
+                self.pending.extend(self.next_depth.drain(..));
+            }
+
+            // limit how many branches grow at once so each one still moves visibly
+            while self.active.len() < params.max_growth_fronts.max(1) {
+                match self.pending.pop_front() {
+                    Some(front) => self.active.push(front),
+                    None => break,
+                }
+            }
+
+            let share = *distance / self.active.len() as f64;
+            let mut spawned = Vec::new();
+            *distance = 0.0;
+
+            for mut front in std::mem::take(&mut self.active) {
+                let mut front_distance = share;
+                let finished = draw_lsystem_substring(
+                    &front.current_string,
+                    &mut front.turtle_state,
+                    params,
+                    screen,
+                    &mut front_distance,
+                    drawing,
+                    &mut spawned,
+                );
+
+                // a branch that finishes early hands what's left of its share back
+                *distance += front_distance;
+                if finished {
+                    turtle_state.pos = front.turtle_state.pos;
+                } else {
+                    self.active.push(front);
+                }
+            }
+
+            match params.drawing_order {
+                DrawingOrder::BreadthFirst => self.next_depth.extend(spawned),
+                _ => self.pending.extend(spawned),
+            }
+        }
+    }
+}
@@ -0,0 +1,392 @@
+use rand::prelude::*;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::f64::consts::{FRAC_PI_3, FRAC_PI_4};
+
+use super::*;
+
+// Once the piece the camera is zooming into is this many times longer than the screen is
+// wide every piece is swapped for its children so the numbers never get too big
+const MAX_ROOT_LENGTH: f64 = 4.0;
+
+// How quickly the picture grows, it doubles in size about every 3 seconds
+const ZOOM_SPEED: f64 = 0.23;
+
+// What fraction of the way to the middle of the screen the focus moves in a second
+const PAN_EASING: f64 = 0.5;
+
+// How many generations below the focus piece the focus point is worked out to. More
+// than enough for it to stay still to well under a pixel.
+const FOCUS_DEPTH: usize = 40;
+
+// How much of the screen the figure fills at the start
+const START_FILL: f64 = 0.6;
+
+// The palette goes round once every time the distance from the focus gets this many times
+// bigger, so bands of colour stream outwards as the camera zooms in
+const COLOUR_BAND: f64 = 4.0;
+
+const FRACTALS: [Fractal; 3] = [Fractal::Koch, Fractal::Dragon, Fractal::Gosper];
+
+// Lsystems that are exactly self-similar. Every symbol that draws is replaced by a smaller
+// copy of the curve that starts and ends where it did, so the figure can be expanded one
+// piece at a time instead of one whole generation at a time.
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+pub enum Fractal {
+    Koch,   // the snowflake
+    Dragon, // the Heighway dragon
+    Gosper, // the flowsnake
+    Any,    // pick one of the above at random
+}
+
+impl Fractal {
+    // the angle, the start string and the rules, every char in the rules that isn't a turn
+    // draws a line
+    fn lsystem(self) -> (f64, &'static str, &'static [(char, &'static str)]) {
+        match self {
+            Fractal::Koch => (FRAC_PI_3, "F--F--F", &[('F', "F+F--F+F")]),
+            Fractal::Dragon => (FRAC_PI_4, "L", &[('L', "+L--R+"), ('R', "-L++R-")]),
+            Fractal::Gosper | Fractal::Any => (
+                FRAC_PI_3,
+                "A",
+                &[('A', "A-B--B+A++AA+B-"), ('B', "+A-BB--B-A++A+B")],
+            ),
+        }
+    }
+
+    // Pieces shorter than this many pixels are drawn as a line rather than expanded any
+    // further. The dragon and flowsnake fill the plane so they need far more lines to cover
+    // the screen at the same length.
+    fn leaf_length(self) -> f64 {
+        match self {
+            Fractal::Koch => 4.0,
+            Fractal::Dragon | Fractal::Gosper | Fractal::Any => 16.0,
+        }
+    }
+}
+
+// A line in the figure that is either drawn or replaced by its children. The children of
+// a symbol keep the same shape relative to its from and to however big it gets.
+#[derive(Copy, Clone)]
+struct Piece {
+    symbol: char,
+    from: [f64; 2],
+    to: [f64; 2],
+}
+
+// Zooms forever into a random point of a self-similar lsystem. Only the pieces that could
+// reach the screen are ever expanded so the figure can be infinitely detailed.
+pub struct InfiniteZoom {
+    children: HashMap<char, Vec<Piece>>, // each symbol's children for a piece from 0, 0 to 1, 0
+    reach: f64, // how far from its middle a piece's curve can get, as a multiple of its length
+    leaf_length: f64,
+    roots: Vec<Piece>, // the biggest pieces that can still be seen
+    spare: Vec<Piece>, // the roots before the last expansion, kept to reuse their memory
+    focus: usize,      // which root is being zoomed into
+    path: Vec<usize>,  // which child to take at each level on the way down to the focus
+    zoomed: f64,       // the log of how much bigger everything is than at the start
+    palette: Palette,
+}
+
+impl InfiniteZoom {
+    pub fn new(fractal: Fractal, params: &Parameters, screen: Size, rng: &mut ThreadRng) -> Self {
+        let fractal = match fractal {
+            Fractal::Any => *FRACTALS.choose(rng).unwrap(),
+            fractal => fractal,
+        };
+
+        let (angle, start, rules) = fractal.lsystem();
+        let mut children = HashMap::new();
+        for &(symbol, rule) in rules {
+            // scale and turn the rule's curve so it goes from 0, 0 to 1, 0 like its parent
+            let pieces = trace(rule, angle);
+            let end = pieces.last().unwrap().to;
+            let pieces = pieces
+                .iter()
+                .map(|piece| Piece {
+                    from: divide(piece.from, end),
+                    to: divide(piece.to, end),
+                    ..*piece
+                })
+                .collect();
+            children.insert(symbol, pieces);
+        }
+
+        let mut zoom = InfiniteZoom {
+            reach: reach(&children),
+            leaf_length: fractal.leaf_length(),
+            children,
+            roots: trace(start, angle),
+            spare: Vec::new(),
+            focus: 0,
+            path: Vec::new(),
+            zoomed: 0.0,
+            palette: Palette::new(params.palette, rng),
+        };
+        zoom.fit(screen);
+        zoom.focus = rng.gen_range(0, zoom.roots.len());
+        zoom
+    }
+
+    // Scales the start of the figure to fit in the middle of the screen
+    fn fit(&mut self, screen: Size) {
+        let (mut min, mut max) = ([f64::MAX; 2], [f64::MIN; 2]);
+        for piece in self.roots.iter() {
+            for point in [piece.from, piece.to].iter() {
+                for axis in 0..2 {
+                    min[axis] = min[axis].min(point[axis]);
+                    max[axis] = max[axis].max(point[axis]);
+                }
+            }
+        }
+
+        let size = (max[0] - min[0]).max(max[1] - min[1]);
+        let scale = screen.width.min(screen.height) * START_FILL / size;
+        let offset = [
+            screen.width / 2.0 - (min[0] + max[0]) / 2.0 * scale,
+            screen.height / 2.0 - (min[1] + max[1]) / 2.0 * scale,
+        ];
+        self.transform(|point| [offset[0] + point[0] * scale, offset[1] + point[1] * scale]);
+    }
+
+    pub fn update(&mut self, dt: f64, screen: Size, rng: &mut ThreadRng) {
+        // zoom in on the focus while bringing it to the middle of the screen
+        let focus = self.focus_point(rng);
+        let growth = (ZOOM_SPEED * dt).exp();
+        let easing = 1.0 - (1.0 - PAN_EASING).powf(dt);
+        let pan = [
+            (screen.width / 2.0 - focus[0]) * easing,
+            (screen.height / 2.0 - focus[1]) * easing,
+        ];
+        self.transform(|point| {
+            [
+                focus[0] + (point[0] - focus[0]) * growth + pan[0],
+                focus[1] + (point[1] - focus[1]) * growth + pan[1],
+            ]
+        });
+        self.zoomed += ZOOM_SPEED * dt;
+
+        // a long pause between updates can grow the focus root by more than one generation
+        while length(&self.roots[self.focus]) > screen.width * MAX_ROOT_LENGTH {
+            self.expand_roots(screen, rng);
+        }
+    }
+
+    // Swaps every root for those of its children that can still be seen
+    fn expand_roots(&mut self, screen: Size, rng: &mut ThreadRng) {
+        let mut roots = std::mem::take(&mut self.spare);
+        roots.clear();
+        let mut focus = 0;
+        let focus_child = self.path_step(0, self.roots[self.focus].symbol, rng);
+        for (index, root) in self.roots.iter().enumerate() {
+            for (child_index, child) in self.children_of(root).enumerate() {
+                if index == self.focus && child_index == focus_child {
+                    focus = roots.len();
+                    roots.push(child);
+                } else if self.visible(&child, screen) {
+                    roots.push(child);
+                }
+            }
+        }
+
+        self.spare = std::mem::replace(&mut self.roots, roots);
+        self.focus = focus;
+        self.path.remove(0);
+    }
+
+    // The point being zoomed into, the start of a piece far below the focus root
+    fn focus_point(&mut self, rng: &mut ThreadRng) -> [f64; 2] {
+        let mut piece = self.roots[self.focus];
+        for depth in 0..FOCUS_DEPTH {
+            let child = self.path_step(depth, piece.symbol, rng);
+            piece = self.children_of(&piece).nth(child).unwrap();
+        }
+
+        piece.from
+    }
+
+    // Which child the path to the focus takes depth levels below the focus root, picking one
+    // at random the first time it's needed
+    fn path_step(&mut self, depth: usize, symbol: char, rng: &mut ThreadRng) -> usize {
+        while self.path.len() <= depth {
+            let choices = self.children[&symbol].len();
+            self.path.push(rng.gen_range(0, choices));
+        }
+
+        self.path[depth]
+    }
+
+    fn children_of<'a>(&'a self, piece: &Piece) -> impl Iterator<Item = Piece> + 'a {
+        let from = piece.from;
+        let along = [piece.to[0] - from[0], piece.to[1] - from[1]];
+        let place = move |point: [f64; 2]| {
+            let turned = multiply(point, along);
+            [from[0] + turned[0], from[1] + turned[1]]
+        };
+
+        self.children[&piece.symbol].iter().map(move |child| Piece {
+            symbol: child.symbol,
+            from: place(child.from),
+            to: place(child.to),
+        })
+    }
+
+    // Whether anything the piece turns into could end up on the screen
+    fn visible(&self, piece: &Piece, screen: Size) -> bool {
+        let middle = [
+            (piece.from[0] + piece.to[0]) / 2.0,
+            (piece.from[1] + piece.to[1]) / 2.0,
+        ];
+        let radius = length(piece) * self.reach;
+        let outside_x = (-middle[0]).max(middle[0] - screen.width).max(0.0);
+        let outside_y = (-middle[1]).max(middle[1] - screen.height).max(0.0);
+        outside_x * outside_x + outside_y * outside_y <= radius * radius
+    }
+
+    fn transform<T>(&mut self, transform: T)
+    where
+        T: Fn([f64; 2]) -> [f64; 2],
+    {
+        for piece in self.roots.iter_mut() {
+            piece.from = transform(piece.from);
+            piece.to = transform(piece.to);
+        }
+    }
+
+    // Adds everything on the screen to the batch expanded down to lines a few pixels long
+    pub fn draw(&self, screen: Size, params: &Parameters, batch: &mut SegmentBatch) {
+        let centre = [screen.width / 2.0, screen.height / 2.0];
+        for root in self.roots.iter() {
+            self.expand(root, screen, centre, params, batch);
+        }
+    }
+
+    fn expand(
+        &self,
+        piece: &Piece,
+        screen: Size,
+        centre: [f64; 2],
+        params: &Parameters,
+        batch: &mut SegmentBatch,
+    ) {
+        if !self.visible(piece, screen) {
+            return;
+        }
+
+        if length(piece) > self.leaf_length {
+            for child in self.children_of(piece) {
+                self.expand(&child, screen, centre, params, batch);
+            }
+            return;
+        }
+
+        let from_centre = (piece.from[0] - centre[0]).hypot(piece.from[1] - centre[1]);
+        let band = (from_centre.max(1.0).ln() - self.zoomed) / COLOUR_BAND.ln();
+        batch.push(&Segment {
+            from: [piece.from[0], piece.from[1], 0.0],
+            to: [piece.to[0], piece.to[1], 0.0],
+            colour: self.palette.sample(band),
+            width: params.line_width,
+        });
+    }
+}
+
+// Follows the string with unit length lines starting at 0, 0 facing along x
+fn trace(string: &str, angle: f64) -> Vec<Piece> {
+    let mut pieces = Vec::new();
+    let (mut position, mut heading) = ([0.0, 0.0], 0.0f64);
+    for symbol in string.chars() {
+        match symbol {
+            '+' => heading += angle,
+            '-' => heading -= angle,
+            _ => {
+                let to = [position[0] + heading.cos(), position[1] + heading.sin()];
+                pieces.push(Piece {
+                    symbol,
+                    from: position,
+                    to,
+                });
+                position = to;
+            }
+        }
+    }
+
+    pieces
+}
+
+// The smallest multiple of a piece's length that a circle round its middle has to be to
+// hold its children's circles, and so everything it will ever turn into
+fn reach(children: &HashMap<char, Vec<Piece>>) -> f64 {
+    let mut reach = 0.5;
+    for _ in 0..1000 {
+        let mut next: f64 = 0.5;
+        for child in children.values().flatten() {
+            let middle = [
+                (child.from[0] + child.to[0]) / 2.0 - 0.5,
+                (child.from[1] + child.to[1]) / 2.0,
+            ];
+            next = next.max(middle[0].hypot(middle[1]) + length(child) * reach);
+        }
+        reach = next;
+    }
+
+    reach
+}
+
+fn length(piece: &Piece) -> f64 {
+    (piece.to[0] - piece.from[0]).hypot(piece.to[1] - piece.from[1])
+}
+
+// Points as complex numbers, multiplying by a number turns and scales by it
+fn multiply(a: [f64; 2], b: [f64; 2]) -> [f64; 2] {
+    [a[0] * b[0] - a[1] * b[1], a[0] * b[1] + a[1] * b[0]]
+}
+
+fn divide(a: [f64; 2], b: [f64; 2]) -> [f64; 2] {
+    let norm = b[0] * b[0] + b[1] * b[1];
+    multiply(a, [b[0] / norm, -b[1] / norm])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Everything a piece turns into, however far down, stays inside the circle that
+    // visible() relies on
+    #[test]
+    fn descendants_stay_within_reach() {
+        let screen = Size {
+            width: 100.0,
+            height: 100.0,
+        };
+        let params = Parameters::new();
+        for &fractal in FRACTALS.iter() {
+            let zoom = InfiniteZoom::new(fractal, &params, screen, &mut thread_rng());
+            for &symbol in zoom.children.keys() {
+                let mut pieces = vec![Piece {
+                    symbol,
+                    from: [0.0, 0.0],
+                    to: [1.0, 0.0],
+                }];
+                for _ in 0..6 {
+                    pieces = pieces
+                        .iter()
+                        .flat_map(|piece| zoom.children_of(piece))
+                        .collect();
+                    for piece in pieces.iter() {
+                        for point in [piece.from, piece.to].iter() {
+                            let distance = (point[0] - 0.5).hypot(point[1]);
+                            assert!(
+                                distance <= zoom.reach + 1e-9,
+                                "{:?} reaches {} past {}",
+                                fractal,
+                                distance,
+                                zoom.reach
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
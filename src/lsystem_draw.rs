@@ -1,110 +1,436 @@
 use super::*;
+use std::f64::consts::{PI, TAU};
 
-pub fn draw_lsystem_substring<G>(
-    string: &String,
-    angle: f64,
+// Moves the turtle through the substring and outputs every line it draws as a segment and
+// every polygon and glyph it fills as a polygon.
+// The turtle only moves as far as distance allows so it may stop part way through an F and
+// carry on from there next time. Returns true once the whole substring has been drawn.
+// Unless the drawing order is sequential every bracketed branch is handed off to a new
+// growth front in spawned rather than being drawn here.
+pub fn draw_lsystem_substring(
+    current_string: &CurrentString,
     turtle_state: &mut TurtleState,
     params: &Parameters,
-    context: Context,
-    graphics: &mut G,
-) where
-    G: Graphics,
-{
-    use graphics::*;
-
-    let mut current_angle = turtle_state.pos.angle;
-    let mut x = turtle_state.pos.x;
-    let mut y = turtle_state.pos.y;
-
-    for ch in string.chars() {
-        match ch {
+    screen: Size,
+    distance: &mut f64,
+    drawing: &mut Drawing,
+    spawned: &mut Vec<GrowthFront>,
+) -> bool {
+    let mut pos = turtle_state.pos;
+    let angle = current_string.angle;
+    let spawn_branches = !matches!(params.drawing_order, DrawingOrder::Sequential);
+    let string = current_string.string.as_bytes(); // lsystem strings are always ascii
+    let mut i = turtle_state.string_pos;
+    let mut finished = true;
+
+    // every turn is a little different, but the same every time this char is drawn
+    let jittered = |i: usize| angle * (1.0 + params.turn_jitter * jitter(current_string, i));
+
+    while i < string.len() {
+        match string[i] as char {
             'F' => {
-                let mut distance_remaining = params.distance_per_movement;
-                let mut next_movement = get_next_pen_movement(
-                    x,
-                    y,
-                    current_angle,
-                    distance_remaining,
-                    WINDOW_WIDTH,
-                    WINDOW_HEIGHT,
-                );
+                let colour = segment_colour(current_string, i, pos.angle, turtle_state, params);
+                let width = params.line_width * pos.width_scale;
+                let length = params.distance_per_movement
+                    * pos.length_scale
+                    * (1.0 + params.length_jitter * jitter(current_string, i)).max(0.0);
+                let remaining = length - turtle_state.segment_progress;
 
-                while next_movement.length < distance_remaining {
-                    line_from_to(
-                        turtle_state.colour,
-                        1.0,
-                        [x, y],
-                        [next_movement.x, next_movement.y],
-                        context.transform,
-                        graphics,
-                    );
-                    match next_movement.move_to_x {
-                        Some(move_to_x) => x = move_to_x,
-                        None => break,
-                    };
-
-                    match next_movement.move_to_y {
-                        Some(move_to_y) => y = move_to_y,
-                        None => break,
-                    };
-
-                    distance_remaining -= next_movement.length;
-                    next_movement = get_next_pen_movement(
-                        x,
-                        y,
-                        current_angle,
-                        distance_remaining,
-                        WINDOW_WIDTH,
-                        WINDOW_HEIGHT,
-                    )
+                // inside a polygon the pen only marks out its corners rather than drawing
+                let mut hidden = Vec::new();
+                let segments = if turtle_state.polygon_stack.is_empty() {
+                    &mut drawing.segments
+                } else {
+                    &mut hidden
+                };
+
+                if params.smooth_lines && turtle_state.segment_progress == 0.0 {
+                    let geometry = params.geometry;
+                    turtle_state
+                        .pen
+                        .start(&pos, length, width, colour, geometry, screen);
                 }
 
-                line_from_to(
-                    turtle_state.colour,
-                    params.line_width,
-                    [x, y],
-                    [next_movement.x, next_movement.y],
-                    context.transform,
-                    graphics,
+                // draw as much of the line as we can and pick it up again next time
+                let partial = *distance < remaining;
+                let moved = if partial { *distance } else { remaining };
+                let mut lines = Vec::new();
+                move_forward(
+                    &mut pos,
+                    moved,
+                    width,
+                    colour,
+                    params.geometry,
+                    screen,
+                    &mut lines,
                 );
+                turtle_state.distance_travelled += moved;
+                *distance -= moved;
 
-                x = match next_movement.move_to_x {
-                    Some(move_to_x) => move_to_x,
-                    None => next_movement.x,
-                };
+                // a polygon that crosses an edge of the screen would stretch across it
+                // so any that are open are dropped
+                if lines.windows(2).any(|pair| pair[0].to != pair[1].from) {
+                    for polygon in turtle_state.polygon_stack.iter_mut() {
+                        *polygon = None;
+                    }
+                }
 
-                y = match next_movement.move_to_y {
-                    Some(move_to_y) => move_to_y,
-                    None => next_movement.y,
+                // when smoothing the turtle only finds the corners for the pen to round off
+                if params.smooth_lines {
+                    lines.clear();
+                    if partial {
+                        turtle_state
+                            .pen
+                            .draw(moved, params.geometry, screen, &mut lines);
+                    } else {
+                        turtle_state
+                            .pen
+                            .finish(&pos, params.geometry, screen, &mut lines);
+                    }
+                }
+                turtle_state.stroke.draw(lines, segments);
+
+                if partial {
+                    turtle_state.segment_progress += moved;
+                    turtle_state.string_pos = i;
+                    finished = false;
+                    break;
+                }
+
+                turtle_state.segment_progress = 0.0;
+                bend(&mut pos, params);
+
+                if let Some(Some(polygon)) = turtle_state.polygon_stack.last_mut() {
+                    polygon.push([pos.x, pos.y, pos.z]);
+                }
+            }
+            '+' => turn(&mut pos, jittered(i)),
+            '-' => turn(&mut pos, -jittered(i)),
+            '&' => pos.frame.pitch(jittered(i)),
+            '^' => pos.frame.pitch(-jittered(i)),
+            '\\' => pos.frame.roll(jittered(i)),
+            '/' => pos.frame.roll(-jittered(i)),
+            '[' if spawn_branches => {
+                lift_pen(turtle_state, params, screen, drawing);
+                let close = matching_bracket(&current_string.string, i);
+                let mut branch_state = turtle_state.clone();
+                branch_state.position_stack.push(pos);
+                branch_state.pos = Position {
+                    width_scale: pos.width_scale * current_string.width_ratio,
+                    length_scale: pos.length_scale * current_string.length_ratio,
+                    ..pos
                 };
+                branch_state.string_pos = 0;
+                branch_state.segment_progress = 0.0;
+
+                spawned.push(GrowthFront {
+                    turtle_state: branch_state,
+                    current_string: current_string.substring(i + 1..close),
+                });
+
+                // the branch returns to where it started so we carry on as if it's been drawn
+                i = close;
+            }
+            '[' => {
+                lift_pen(turtle_state, params, screen, drawing);
+                turtle_state.position_stack.push(pos);
+
+                // branches get thinner and shorter the deeper they go
+                pos.width_scale *= current_string.width_ratio;
+                pos.length_scale *= current_string.length_ratio;
             }
-            '+' => current_angle += angle,
-            '-' => current_angle -= angle,
-            '[' => turtle_state.position_stack.push(Position {
-                x,
-                y,
-                angle: current_angle,
-            }),
             ']' => {
+                lift_pen(turtle_state, params, screen, drawing);
+
                 // this creates those tree-like patterns
                 if let Some(state) = turtle_state.position_stack.pop() {
-                    x = state.x;
-                    y = state.y;
-                    current_angle = state.angle;
+                    pos = state;
+                }
+            }
+            '{' => turtle_state
+                .polygon_stack
+                .push(Some(vec![[pos.x, pos.y, pos.z]])),
+            '}' => {
+                if let Some(Some(points)) = turtle_state.polygon_stack.pop() {
+                    let colour = segment_colour(current_string, i, pos.angle, turtle_state, params);
+                    drawing.polygons.push(Polygon::new(points, colour));
+                }
+            }
+            ch => {
+                if let Some(glyph) = Glyph::from_char(ch) {
+                    let colour = segment_colour(current_string, i, pos.angle, turtle_state, params);
+                    let size = params.distance_per_movement * pos.length_scale;
+                    glyph.stamp(&pos, params.geometry, size, colour, drawing);
                 }
             }
-            _ => {} // do nothing
         };
+
+        i += 1;
     }
 
-    turtle_state.pos = Position {
-        x,
-        y,
-        angle: current_angle,
+    if finished {
+        turtle_state.string_pos = 0;
+
+        // a sequential turtle is handed the string a piece at a time so its runs of F's
+        // carry on into the next piece, any other substring ends with its last run
+        let whole_string_drawn =
+            current_string.string_pos + string.len() >= current_string.string_len;
+        if spawn_branches || whole_string_drawn {
+            lift_pen(turtle_state, params, screen, drawing);
+        }
+    }
+
+    turtle_state.pos = pos;
+    finished
+}
+
+// Ends the pen's current run of F's, see Pen
+fn lift_pen(
+    turtle_state: &mut TurtleState,
+    params: &Parameters,
+    screen: Size,
+    drawing: &mut Drawing,
+) {
+    let mut hidden = Vec::new();
+    let segments = if turtle_state.polygon_stack.is_empty() {
+        &mut drawing.segments
+    } else {
+        &mut hidden
     };
+
+    let mut lines = Vec::new();
+    turtle_state.pen.lift(params.geometry, screen, &mut lines);
+    turtle_state.stroke.draw(lines, segments);
+}
+
+// Returns a normally distributed random number for the char at char_index that only
+// depends on the system's jitter seed and where the char is in the whole string
+fn jitter(current_string: &CurrentString, char_index: usize) -> f64 {
+    let index = (current_string.string_pos + char_index) as u64;
+    let first = split_mix(current_string.jitter_seed ^ index.wrapping_mul(2));
+    let second = split_mix(current_string.jitter_seed ^ index.wrapping_mul(2).wrapping_add(1));
+
+    // Box-Muller transform, the first uniform number is kept away from zero for the log
+    let uniform = |bits: u64| ((bits >> 11) as f64 + 0.5) / (1u64 << 53) as f64;
+    (-2.0 * uniform(first).ln()).sqrt() * (TAU * uniform(second)).cos()
+}
+
+// See http://prng.di.unimi.it/splitmix64.c
+fn split_mix(seed: u64) -> u64 {
+    let mut z = seed.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+// Only the 3D turtle has a frame to turn, everything else just has an angle
+fn turn(pos: &mut Position, angle: f64) {
+    pos.angle += angle;
+    pos.frame.turn(angle);
+}
+
+// Returns the index of the ']' that closes the '[' at open, or the end of the string
+fn matching_bracket(string: &str, open: usize) -> usize {
+    let mut depth = 0;
+    for (i, ch) in string.as_bytes()[open..].iter().enumerate() {
+        match ch {
+            b'[' => depth += 1,
+            b']' => {
+                depth -= 1;
+                if depth == 0 {
+                    return open + i;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    string.len()
+}
+
+// Bends the heading towards the tropism vector after every F like gravity or wind bending
+// a plant. The more the turtle faces across the vector the more it bends.
+fn bend(pos: &mut Position, params: &Parameters) {
+    let tropism = params.tropism;
+    let susceptibility = params.tropism_susceptibility;
+    if susceptibility == 0.0 {
+        return;
+    }
+
+    match params.geometry {
+        Geometry::Space => {
+            let axis = cross(pos.frame.heading, tropism);
+            let torque = dot(axis, axis).sqrt();
+            if torque > 0.0 {
+                pos.frame
+                    .rotate(scale(axis, 1.0 / torque), susceptibility * torque);
+            }
+        }
+        _ => {
+            // only the part of the vector in the plane of the screen can bend a flat turtle
+            let torque = pos.angle.cos() * tropism[1] - pos.angle.sin() * tropism[0];
+            pos.angle += susceptibility * torque;
+        }
+    }
+}
+
+// Draws the straightest line there is from pos in the turtle's geometry and moves the pen
+// to where it ends up, some geometries also change which way it's facing
+pub fn move_forward(
+    pos: &mut Position,
+    distance: f64,
+    width: f64,
+    colour: [f32; 4],
+    geometry: Geometry,
+    screen: Size,
+    segments: &mut Vec<Segment>,
+) {
+    let (x, y, angle) = (pos.x, pos.y, pos.angle);
+    let (x, y, angle) = match geometry {
+        Geometry::Flat(topology) => wrap_forward(
+            x, y, angle, distance, width, colour, topology, screen, segments,
+        ),
+        Geometry::Hyperbolic => {
+            hyperbolic_forward(x, y, angle, distance, width, colour, screen, segments)
+        }
+        Geometry::Spherical => {
+            spherical_forward(x, y, angle, distance, width, colour, screen, segments)
+        }
+        Geometry::Space => {
+            let to = space_forward(pos, distance, width, colour, segments);
+            pos.z = to[2];
+            (to[0], to[1], angle)
+        }
+    };
+
+    pos.x = x;
+    pos.y = y;
+    pos.angle = angle;
+}
+
+// Draws a straight line from x, y wrapping around the edges of the screen as needed
+// and returns where the pen ends up and which way it's facing. Crossing an edge can mirror
+// the pen depending on how the topology joins the edges together.
+#[allow(clippy::too_many_arguments)]
+fn wrap_forward(
+    mut x: f64,
+    mut y: f64,
+    mut angle: f64,
+    distance: f64,
+    width: f64,
+    colour: [f32; 4],
+    topology: Topology,
+    screen: Size,
+    segments: &mut Vec<Segment>,
+) -> (f64, f64, f64) {
+    let mut distance_remaining = distance;
+    loop {
+        let next_movement =
+            get_next_pen_movement(x, y, angle, distance_remaining, screen.width, screen.height);
+
+        match next_movement.edge {
+            Some(edge) => {
+                segments.push(Segment {
+                    from: [x, y, 0.0],
+                    to: [next_movement.x, next_movement.y, 0.0],
+                    colour,
+                    width,
+                });
+
+                let (new_x, new_y, new_angle) = cross_edge(
+                    topology,
+                    edge,
+                    next_movement.x,
+                    next_movement.y,
+                    angle,
+                    screen,
+                );
+                x = new_x;
+                y = new_y;
+                angle = new_angle;
+                distance_remaining -= next_movement.length;
+            }
+            None => {
+                segments.push(Segment {
+                    from: [x, y, 0.0],
+                    to: [next_movement.x, next_movement.y, 0.0],
+                    colour,
+                    width,
+                });
+
+                return (next_movement.x, next_movement.y, angle);
+            }
+        }
+    }
+}
+
+// Picks the pen colour for the F at char_index according to the colour mode
+fn segment_colour(
+    current_string: &CurrentString,
+    char_index: usize,
+    angle: f64,
+    turtle_state: &TurtleState,
+    params: &Parameters,
+) -> [f32; 4] {
+    match params.colour_mode {
+        ColourMode::Solid => turtle_state.colour,
+        ColourMode::Path => turtle_state
+            .palette
+            .sample(turtle_state.distance_travelled / params.gradient_length),
+        ColourMode::Depth => turtle_state
+            .palette
+            .sample(turtle_state.position_stack.len() as f64 / params.gradient_depth as f64),
+        ColourMode::Progress => turtle_state.palette.sample(
+            (current_string.string_pos + char_index) as f64 / current_string.string_len as f64,
+        ),
+        ColourMode::Heading => turtle_state.palette.sample(angle / TAU),
+        ColourMode::Generation => turtle_state.palette.sample(
+            current_string.generations[char_index] as f64
+                / (current_string.max_generation as f64 + 1.0),
+        ),
+    }
 }
 
-// FIXME: remove duplication in this function
+// Returns where the pen comes back onto the screen after leaving it at x, y through edge
+// and which way it's facing then
+fn cross_edge(
+    topology: Topology,
+    edge: Edge,
+    x: f64,
+    y: f64,
+    angle: f64,
+    screen: Size,
+) -> (f64, f64, f64) {
+    let (left_right, top_bottom) = topology.identifications();
+    match edge {
+        Edge::Left | Edge::Right => {
+            let opposite_x = match edge {
+                Edge::Left => screen.width,
+                _ => 0.0,
+            };
+
+            match left_right {
+                Identification::Straight => (opposite_x, y, angle),
+                Identification::Twisted => (opposite_x, screen.height - y, -angle),
+                Identification::Wall => (screen.width - opposite_x, y, PI - angle),
+            }
+        }
+        Edge::Top | Edge::Bottom => {
+            let opposite_y = match edge {
+                Edge::Top => screen.height,
+                _ => 0.0,
+            };
+
+            match top_bottom {
+                Identification::Straight => (x, opposite_y, angle),
+                Identification::Twisted => (screen.width - x, opposite_y, PI - angle),
+                Identification::Wall => (x, screen.height - opposite_y, -angle),
+            }
+        }
+    }
+}
+
+// Works out how far the pen can move before it leaves the screen and which edge it leaves by
 fn get_next_pen_movement(
     x: f64,
     y: f64,
@@ -115,74 +441,45 @@ fn get_next_pen_movement(
 ) -> PossibleMovement {
     let cos_angle = angle.cos();
     let sin_angle = angle.sin();
-    let mut new_x = x + cos_angle * distance;
-    let mut new_y = y + sin_angle * distance;
-    let too_left = new_x < 0.0;
-    let too_right = new_x > max_x;
-    let too_up = new_y < 0.0;
-    let too_down = new_y > max_y;
-
-    let mut dist_until_out_of_bounds;
-    let mut possible_movements = vec![PossibleMovement {
+    let new_x = x + cos_angle * distance;
+    let new_y = y + sin_angle * distance;
+
+    // the lengths are only used when the pen really does cross that edge so they are never
+    // divided by zero
+    let crossings = [
+        (new_x < 0.0, -x / cos_angle, Edge::Left),
+        (new_x > max_x, (max_x - x) / cos_angle, Edge::Right),
+        (new_y < 0.0, -y / sin_angle, Edge::Top),
+        (new_y > max_y, (max_y - y) / sin_angle, Edge::Bottom),
+    ];
+
+    let mut movement = PossibleMovement {
         x: new_x,
         y: new_y,
         length: distance,
-        move_to_x: None,
-        move_to_y: None,
-    }];
-
-    if too_right || too_left {
-        let move_to_x;
-        if too_right {
-            dist_until_out_of_bounds = (max_x - x) / cos_angle;
-            move_to_x = 0.0;
-        } else {
-            dist_until_out_of_bounds = -x / cos_angle;
-            move_to_x = max_x;
-        }
+        edge: None,
+    };
 
-        new_x = x + cos_angle * dist_until_out_of_bounds;
-        new_y = y + sin_angle * dist_until_out_of_bounds;
-
-        possible_movements.push(PossibleMovement {
-            x: new_x,
-            y: new_y,
-            length: dist_until_out_of_bounds,
-            move_to_x: Some(move_to_x),
-            move_to_y: Some(new_y),
-        });
-    }
-
-    if too_down || too_up {
-        let move_to_y;
-        if too_down {
-            dist_until_out_of_bounds = (max_y - y) / sin_angle;
-            move_to_y = 0.0;
-        } else {
-            dist_until_out_of_bounds = -y / sin_angle;
-            move_to_y = max_y;
+    for &(crossed, length, edge) in crossings.iter() {
+        if crossed && length < movement.length {
+            movement = PossibleMovement {
+                x: x + cos_angle * length,
+                y: y + sin_angle * length,
+                length,
+                edge: Some(edge),
+            };
         }
-
-        new_x = x + cos_angle * dist_until_out_of_bounds;
-        new_y = y + sin_angle * dist_until_out_of_bounds;
-
-        possible_movements.push(PossibleMovement {
-            x: new_x,
-            y: new_y,
-            length: dist_until_out_of_bounds,
-            move_to_x: Some(new_x),
-            move_to_y: Some(move_to_y),
-        });
     }
 
-    let mut min_possible_movement = possible_movements.first().unwrap();
-    for possible_movement in possible_movements.iter() {
-        if possible_movement.length < min_possible_movement.length {
-            min_possible_movement = possible_movement
-        }
-    }
+    movement
+}
 
-    *min_possible_movement
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Edge {
+    Left,
+    Right,
+    Top,
+    Bottom,
 }
 
 #[derive(Copy, Clone)]
@@ -190,6 +487,181 @@ struct PossibleMovement {
     x: f64,
     y: f64,
     length: f64,
-    move_to_x: Option<f64>,
-    move_to_y: Option<f64>,
+    edge: Option<Edge>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCREEN: Size = Size {
+        width: 100.0,
+        height: 50.0,
+    };
+
+    fn assert_near(actual: (f64, f64, f64), expected: (f64, f64, f64)) {
+        let angle_difference = (actual.2 - expected.2).rem_euclid(TAU);
+        assert!(
+            (actual.0 - expected.0).abs() < 1e-9
+                && (actual.1 - expected.1).abs() < 1e-9
+                && angle_difference.min(TAU - angle_difference) < 1e-9,
+            "expected {:?} but got {:?}",
+            expected,
+            actual
+        );
+    }
+
+    #[test]
+    fn pen_leaves_by_the_nearest_edge() {
+        let movement = get_next_pen_movement(90.0, 45.0, FRAC_PI_4, 20.0, 100.0, 50.0);
+        assert_eq!(movement.edge, Some(Edge::Bottom));
+        assert!((movement.x - 95.0).abs() < 1e-9 && (movement.y - 50.0).abs() < 1e-9);
+
+        let movement = get_next_pen_movement(50.0, 25.0, 0.3, 20.0, 100.0, 50.0);
+        assert_eq!(movement.edge, None);
+    }
+
+    #[test]
+    fn torus_enters_opposite_edge_unchanged() {
+        let topology = Topology::Torus;
+        let cross = |edge, x, y| cross_edge(topology, edge, x, y, 0.3, SCREEN);
+        assert_near(cross(Edge::Right, 100.0, 10.0), (0.0, 10.0, 0.3));
+        assert_near(cross(Edge::Left, 0.0, 10.0), (100.0, 10.0, 0.3));
+        assert_near(cross(Edge::Top, 20.0, 0.0), (20.0, 50.0, 0.3));
+        assert_near(cross(Edge::Bottom, 20.0, 50.0), (20.0, 0.0, 0.3));
+    }
+
+    #[test]
+    fn klein_bottle_mirrors_left_and_right_edges_only() {
+        let topology = Topology::KleinBottle;
+        let cross = |edge, x, y| cross_edge(topology, edge, x, y, 0.3, SCREEN);
+        assert_near(cross(Edge::Right, 100.0, 10.0), (0.0, 40.0, -0.3));
+        assert_near(cross(Edge::Left, 0.0, 10.0), (100.0, 40.0, -0.3));
+        assert_near(cross(Edge::Top, 20.0, 0.0), (20.0, 50.0, 0.3));
+        assert_near(cross(Edge::Bottom, 20.0, 50.0), (20.0, 0.0, 0.3));
+    }
+
+    #[test]
+    fn mobius_strip_bounces_off_top_and_bottom() {
+        let topology = Topology::MobiusStrip;
+        let cross = |edge, x, y, angle| cross_edge(topology, edge, x, y, angle, SCREEN);
+        assert_near(cross(Edge::Right, 100.0, 10.0, 0.3), (0.0, 40.0, -0.3));
+        assert_near(cross(Edge::Top, 20.0, 0.0, -1.0), (20.0, 0.0, 1.0));
+        assert_near(cross(Edge::Bottom, 20.0, 50.0, 1.0), (20.0, 50.0, -1.0));
+    }
+
+    #[test]
+    fn projective_plane_mirrors_every_edge() {
+        let topology = Topology::ProjectivePlane;
+        let cross = |edge, x, y, angle| cross_edge(topology, edge, x, y, angle, SCREEN);
+        assert_near(cross(Edge::Right, 100.0, 10.0, 0.3), (0.0, 40.0, -0.3));
+        assert_near(cross(Edge::Top, 20.0, 0.0, -1.0), (80.0, 50.0, PI + 1.0));
+        assert_near(cross(Edge::Bottom, 20.0, 50.0, 1.0), (80.0, 0.0, PI - 1.0));
+    }
+
+    #[test]
+    fn crossing_a_twisted_edge_twice_gets_back_to_the_start() {
+        // one full trip across a Klein bottle ends up mirrored, two trips undo it
+        let mut segments = Vec::new();
+        let start = (30.0, 10.0, 0.0);
+        let once = wrap_forward(
+            start.0,
+            start.1,
+            start.2,
+            100.0,
+            1.0,
+            [1.0; 4],
+            Topology::KleinBottle,
+            SCREEN,
+            &mut segments,
+        );
+        assert_near(once, (30.0, 40.0, 0.0));
+
+        let twice = wrap_forward(
+            once.0,
+            once.1,
+            once.2,
+            100.0,
+            1.0,
+            [1.0; 4],
+            Topology::KleinBottle,
+            SCREEN,
+            &mut segments,
+        );
+        assert_near(twice, start);
+
+        // every piece of the line stays on the screen
+        for segment in segments.iter() {
+            for point in [segment.from, segment.to].iter() {
+                assert!(point[0] > -1e-9 && point[0] < 100.0 + 1e-9);
+                assert!(point[1] > -1e-9 && point[1] < 50.0 + 1e-9);
+            }
+        }
+    }
+
+    // Everything drawn for string starting from x, y
+    fn draw_from(string: &str, params: &Parameters, jitter_seed: u64, x: f64, y: f64) -> Drawing {
+        let current_string = CurrentString {
+            string: string.to_string(),
+            generations: vec![0; string.len()],
+            max_generation: 0,
+            string_pos: 0,
+            string_len: string.len(),
+            angle: 0.4,
+            width_ratio: 1.0,
+            length_ratio: 1.0,
+            jitter_seed,
+        };
+
+        let mut turtle_state = TurtleState::new(params, &mut thread_rng());
+        turtle_state.pos.x = x;
+        turtle_state.pos.y = y;
+        let mut drawing = Drawing::new();
+        let mut distance = f64::INFINITY;
+        draw_lsystem_substring(
+            &current_string,
+            &mut turtle_state,
+            params,
+            SCREEN,
+            &mut distance,
+            &mut drawing,
+            &mut Vec::new(),
+        );
+        drawing
+    }
+
+    // Every point drawn for string with this jitter seed, starting from the middle
+    fn jittered_points(string: &str, jitter_seed: u64) -> Vec<[f64; 3]> {
+        let params = Parameters {
+            turn_jitter: 0.2,
+            length_jitter: 0.2,
+            ..Parameters::new()
+        };
+        draw_from(string, &params, jitter_seed, 50.0, 25.0)
+            .segments
+            .iter()
+            .flat_map(|segment| vec![segment.from, segment.to])
+            .collect()
+    }
+
+    #[test]
+    fn jitter_is_the_same_every_time_for_the_same_seed() {
+        let string = "F+F-F[+F]-F+F";
+        let first = jittered_points(string, 7);
+        assert!(!first.is_empty());
+        assert_eq!(first, jittered_points(string, 7));
+        assert_ne!(first, jittered_points(string, 8));
+    }
+
+    #[test]
+    fn polygons_that_cross_an_edge_are_dropped() {
+        let params = Parameters::new();
+        let inside = draw_from("{F+F+F}", &params, 0, 50.0, 25.0);
+        assert_eq!(inside.polygons.len(), 1);
+        assert_eq!(inside.polygons[0].points.len(), 4);
+
+        // the first side runs off the right edge of the screen and comes back on the left
+        let wrapped = draw_from("{F+F+F}", &params, 0, 95.0, 25.0);
+        assert!(wrapped.polygons.is_empty());
+    }
 }
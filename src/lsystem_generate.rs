@@ -1,34 +1,53 @@
 use rand::prelude::*;
 use std::collections::HashMap;
+use std::ops::Range;
 
 use super::*;
 
 impl LSystem {
-    pub fn new(start: String, rule_map: HashMap<char, String>, angle: f64) -> Self {
+    pub fn new(
+        start: String,
+        rule_map: HashMap<char, String>,
+        angle: f64,
+        width_ratio: f64,
+        length_ratio: f64,
+        jitter_seed: u64,
+    ) -> Self {
         LSystem {
-            seed: start.clone(),
+            generations: vec![0; start.len()],
+            max_generation: 0,
             string: start,
             rules: rule_map,
             angle,
+            width_ratio,
+            length_ratio,
+            jitter_seed,
         }
     }
 
     fn grow(&mut self, max_len: usize) {
         let mut growth_cycle = 0;
         let mut grown_string = self.string.clone(); // FIXME: pretty inefficient to clone this here
+        let mut grown_generations = self.generations.clone();
 
         while grown_string.len() < max_len {
             self.string = grown_string.clone();
+            self.generations = grown_generations.clone();
             let mut grown_string_arr = String::new();
-            for ch in self.string.chars() {
+            let mut grown_generations_arr = Vec::new();
+            for (ch, &generation) in self.string.chars().zip(self.generations.iter()) {
                 if let Some(string) = self.rules.get(&ch) {
+                    // every char produced by a rule remembers which growth cycle created it
                     grown_string_arr.push_str(string.as_str());
+                    grown_generations_arr.resize(grown_string_arr.len(), growth_cycle as u8 + 1);
                 } else {
-                    grown_string_arr.push(ch)
+                    grown_string_arr.push(ch);
+                    grown_generations_arr.push(generation);
                 }
             }
 
             grown_string = grown_string_arr;
+            grown_generations = grown_generations_arr;
             if grown_string == self.string {
                 break;
             }
@@ -39,6 +58,8 @@ impl LSystem {
 
             growth_cycle += 1;
         }
+
+        self.max_generation = self.generations.iter().copied().max().unwrap_or(0);
     }
 }
 
@@ -51,39 +72,55 @@ impl TurtleStates {
             lsys,
         }
     }
+
+    // The whole lsystem string in one piece
+    pub fn whole_string(&self) -> CurrentString {
+        self.current_string(0..self.lsys.string.len())
+    }
+
+    fn current_string(&self, range: Range<usize>) -> CurrentString {
+        CurrentString {
+            string: self.lsys.string[range.clone()].into(),
+            generations: self.lsys.generations[range.clone()].to_vec(),
+            max_generation: self.lsys.max_generation,
+            string_pos: range.start,
+            string_len: self.lsys.string.len(),
+            angle: self.lsys.angle,
+            width_ratio: self.lsys.width_ratio,
+            length_ratio: self.lsys.length_ratio,
+            jitter_seed: self.lsys.jitter_seed,
+        }
+    }
+}
+
+impl CurrentString {
+    pub fn substring(&self, range: Range<usize>) -> CurrentString {
+        CurrentString {
+            string: self.string[range.clone()].into(),
+            generations: self.generations[range.clone()].to_vec(),
+            string_pos: self.string_pos + range.start,
+            ..*self
+        }
+    }
 }
 
 impl Iterator for TurtleStates {
-    type Item = Vec<CurrentString>;
+    type Item = CurrentString;
 
+    // Returns the lsystem string up to and including the next F
     fn next(&mut self) -> Option<Self::Item> {
-        let mut num_fs = 0;
-        let mut current_strings = Vec::new();
-
-        while self.current_string_pos < self.lsys.string.len() {
-            let next_f_pos = match self.lsys.string[self.current_string_pos..].find('F') {
-                Some(pos) => {
-                    num_fs += 1;
-                    self.current_string_pos + pos
-                }
-                None => self.lsys.string.len() - 1,
-            };
-
-            let string: String = self.lsys.string[self.current_string_pos..next_f_pos + 1].into();
-            let current_string = CurrentString {
-                string,
-                angle: self.lsys.angle,
-            };
-
-            current_strings.push(current_string);
-            if num_fs >= FS_PER_TURTLE_MOVE {
-                return Some(current_strings);
-            }
-
-            self.current_string_pos = next_f_pos + 1;
+        if self.current_string_pos >= self.lsys.string.len() {
+            return None;
         }
 
-        None
+        let next_f_pos = match self.lsys.string[self.current_string_pos..].find('F') {
+            Some(pos) => self.current_string_pos + pos,
+            None => self.lsys.string.len() - 1,
+        };
+
+        let current_string = self.current_string(self.current_string_pos..next_f_pos + 1);
+        self.current_string_pos = next_f_pos + 1;
+        Some(current_string)
     }
 }
 
@@ -109,7 +146,7 @@ impl CharSet {
         }
     }
 
-    fn add_chars(&mut self, s: &String) {
+    fn add_chars(&mut self, s: &str) {
         for c in s.chars() {
             if !self.banned_chars.contains(&c) {
                 self.set.push(c)
@@ -123,7 +160,7 @@ impl CharSet {
     }
 
     fn rand_char(&mut self, rng: &mut ThreadRng) -> Option<char> {
-        if self.set.len() == 0 {
+        if self.set.is_empty() {
             None
         } else {
             Some(*rand_choice(&self.set, rng))
@@ -132,25 +169,21 @@ impl CharSet {
 }
 
 fn try_to_create_rule_map(
-    start: &String,
+    start: &str,
     mut rule_strings: Vec<String>,
     rng: &mut ThreadRng,
 ) -> Option<HashMap<char, String>> {
-    let mut used_chars = CharSet::new(vec!['[', ']']);
-    used_chars.add_chars(&start);
+    let mut used_chars = CharSet::new(vec!['[', ']', '{', '}']);
+    used_chars.add_chars(start);
     let mut all_rules = HashMap::new();
 
-    loop {
-        if let Some(rule_string) = rule_strings.pop() {
-            if let Some(rule_key) = used_chars.rand_char(rng) {
-                used_chars.ban_char(rule_key);
-                used_chars.add_chars(&rule_string);
-                all_rules.insert(rule_key, rule_string);
-            } else {
-                return None;
-            }
+    while let Some(rule_string) = rule_strings.pop() {
+        if let Some(rule_key) = used_chars.rand_char(rng) {
+            used_chars.ban_char(rule_key);
+            used_chars.add_chars(&rule_string);
+            all_rules.insert(rule_key, rule_string);
         } else {
-            break;
+            return None;
         }
     }
 
@@ -159,15 +192,28 @@ fn try_to_create_rule_map(
 
 fn rand_lsystem(params: &Parameters, rng: &mut ThreadRng) -> LSystem {
     let angle = rand_angle(params, rng);
+    let width_ratio = rng.gen_range(params.min_branch_width_ratio, params.max_branch_width_ratio);
+    let length_ratio = rng.gen_range(
+        params.min_branch_length_ratio,
+        params.max_branch_length_ratio,
+    );
     let num_rules: usize = rng.gen_range(params.min_rules, params.max_rules);
+    let jitter_seed = rng.gen();
 
     loop {
         let rule_strings = create_random_rule_strings(num_rules, params, rng);
         let num_start_chars: usize =
             rng.gen_range(params.min_start_length, params.max_start_length);
-        let start = rand_lsystem_string(num_start_chars, rng);
+        let start = rand_lsystem_string(num_start_chars, params, rng);
         if let Some(rule_map) = try_to_create_rule_map(&start, rule_strings, rng) {
-            return LSystem::new(start, rule_map, angle);
+            return LSystem::new(
+                start,
+                rule_map,
+                angle,
+                width_ratio,
+                length_ratio,
+                jitter_seed,
+            );
         }
     }
 }
@@ -185,26 +231,44 @@ fn rand_choice_mut<'a, T>(array: &'a mut [T], rng: &mut ThreadRng) -> &'a mut T
     array.iter_mut().choose(rng).unwrap()
 }
 
-fn rand_lsystem_string(len: usize, rng: &mut ThreadRng) -> String {
-    const POSSIBLE_CHARS: [char; 5] = ['F', '+', '-', 'A', 'B'];
-    const SQUARE_BRACKET_CHANCE: f64 = 1.0 / (POSSIBLE_CHARS.len() as f64 + 1.0);
+const POSSIBLE_CHARS: [char; 5] = ['F', '+', '-', 'A', 'B'];
+const SQUARE_BRACKET_CHANCE: f64 = 1.0 / (POSSIBLE_CHARS.len() as f64 + 1.0);
+
+// the 3D turtle can also pitch and roll
+const POSSIBLE_CHARS_3D: [char; 9] = ['F', '+', '-', '&', '^', '\\', '/', 'A', 'B'];
+
+// leaves, flowers and buds stamped where the turtle is, see glyph.rs
+const GLYPH_CHARS: [char; 3] = ['L', 'K', 'O'];
+const CURLY_BRACE_CHANCE: f64 = 0.05;
+
+fn rand_lsystem_string(len: usize, params: &Parameters, rng: &mut ThreadRng) -> String {
+    let mut possible_chars: Vec<char> = match params.geometry {
+        Geometry::Space => POSSIBLE_CHARS_3D.to_vec(),
+        _ => POSSIBLE_CHARS.to_vec(),
+    };
+    if params.plant_parts {
+        possible_chars.extend_from_slice(&GLYPH_CHARS);
+    }
 
     loop {
         let mut num_bracket_pairs = 0;
+        let mut num_brace_pairs = 0;
         for _ in 0..(len as f64 / 2.0).floor() as usize {
             if chance(SQUARE_BRACKET_CHANCE, rng) {
                 num_bracket_pairs += 1;
+            } else if params.plant_parts && chance(CURLY_BRACE_CHANCE, rng) {
+                num_brace_pairs += 1;
             }
         }
 
         let mut string = String::new();
-        let num_random_letters = len - (num_bracket_pairs * 2);
+        let num_random_letters = len - ((num_bracket_pairs + num_brace_pairs) * 2);
         for _ in 0..num_random_letters {
-            let rand_char = rand_choice(&POSSIBLE_CHARS, rng);
+            let rand_char = rand_choice(&possible_chars, rng);
             string.push(*rand_char);
         }
 
-        if string.len() == 0 {
+        if string.is_empty() {
             continue;
         }
 
@@ -216,10 +280,43 @@ fn rand_lsystem_string(len: usize, rng: &mut ThreadRng) -> String {
             string.insert(closing_location + 1, ']'); // FIXME: check that this is correct
         }
 
+        // filled polygons, the outline is wherever the pen goes between the braces. A brace
+        // only closes where the brackets since it opened are balanced so a polygon is never
+        // split between a branch and the rest of the string.
+        for _ in 0..num_brace_pairs {
+            let opening_location: usize = rng.gen_range(0, string.len());
+            let closing_location =
+                *rand_choice(&balanced_ends(&string[opening_location..]), &mut rng)
+                    + opening_location;
+            string.insert(opening_location, '{');
+            string.insert(closing_location + 1, '}');
+        }
+
         return string;
     }
 }
 
+// The lengths of the starts of string that have as many ']' as '[' without a ']' ever
+// coming before its '['
+fn balanced_ends(string: &str) -> Vec<usize> {
+    let mut ends = vec![0];
+    let mut depth = 0;
+    for (i, ch) in string.chars().enumerate() {
+        match ch {
+            '[' => depth += 1,
+            ']' if depth == 0 => break,
+            ']' => depth -= 1,
+            _ => {}
+        }
+
+        if depth == 0 {
+            ends.push(i + 1);
+        }
+    }
+
+    ends
+}
+
 fn create_random_rule_strings(
     num_rules: usize,
     params: &Parameters,
@@ -228,7 +325,7 @@ fn create_random_rule_strings(
     let mut rule_strings = Vec::new();
     for _ in 0..num_rules {
         let len = rng.gen_range(params.min_rule_length, params.max_rule_length);
-        let rule_string = rand_lsystem_string(len, rng);
+        let rule_string = rand_lsystem_string(len, params, rng);
         rule_strings.push(rule_string);
     }
 
@@ -3,10 +3,7 @@
 use rand::prelude::*;
 use std::collections::HashMap;
 use std::env;
-
-// FIXME: Lookup fullscreen resolution instead of hardcoding it
-const WINDOW_HEIGHT: f64 = 1440.0;
-const WINDOW_WIDTH: f64 = 2560.0;
+use std::f64::consts::{FRAC_PI_2, FRAC_PI_3, FRAC_PI_4, FRAC_PI_6};
 
 extern crate glutin_window;
 extern crate graphics;
@@ -14,17 +11,55 @@ extern crate opengl_graphics;
 extern crate piston;
 
 use glutin_window::GlutinWindow as AppWindow;
-use graphics::{Context, Graphics};
 use opengl_graphics::{GlGraphics, OpenGL};
 use piston::event_loop::*;
 use piston::input::*;
-use piston::window::WindowSettings;
+use piston::window::{Size, Window, WindowSettings};
 use piston_window::AdvancedWindow;
-
+use serde::Deserialize;
+
+mod background;
+mod camera;
+mod canvas;
+mod colour;
+mod config;
+mod geometry;
+mod glyph;
+mod growth;
+mod infinite_zoom;
 mod lsystem_draw;
 mod lsystem_generate;
+mod pen;
+mod polygon;
+mod segment_batch;
+mod segment_history;
+mod space;
+mod stroke;
+mod symmetry;
+mod turtle;
+use background::*;
+use camera::*;
+use canvas::*;
+use colour::*;
+use config::*;
+use geometry::*;
+use glyph::*;
+use growth::*;
+use infinite_zoom::*;
 use lsystem_draw::*;
-
+use pen::*;
+use polygon::*;
+use segment_batch::*;
+use segment_history::*;
+use space::*;
+use stroke::*;
+use symmetry::*;
+use turtle::*;
+
+// Every knob of the saver, read from the config file with these defaults for anything it
+// leaves out
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct Parameters {
     // params for rule generation
     min_rules: usize,
@@ -35,12 +70,49 @@ pub struct Parameters {
     max_rule_length: usize,
     lsystem_max_length: usize,
     random_angle_chance: f64,
+    min_branch_width_ratio: f64,
+    max_branch_width_ratio: f64,
+    min_branch_length_ratio: f64,
+    max_branch_length_ratio: f64,
+    plant_parts: bool, // put polygons and leaf, flower and bud glyphs in random lsystems
+    turn_jitter: f64,  // standard deviation of every turn as a fraction of the angle
+    length_jitter: f64, // standard deviation of every line length as a fraction of it
 
     // params for drawing
+    virtual_scale: f64, // screens wide and high the plane the turtles draw on is
+    camera_mode: CameraMode,
+    infinite_zoom: Option<Fractal>, // zoom forever into one of these instead of drawing lsystems
     distance_per_movement: f64,
     line_width: f64,
-    seconds_per_turtle_move: f64,
+    smooth_lines: bool, // round off the corners between F's with curves
+    stroke_style: StrokeStyle,
+    min_pen_speed: f64, // pixels per second
+    max_pen_speed: f64,
+    max_turtles: usize, // turtles drawing at the same time
+    seconds_between_spawns: f64,
+    retire_policy: RetirePolicy,
+    start_position: StartPosition,
+    drawing_order: DrawingOrder,
+    max_growth_fronts: usize, // branches that can grow at once when not sequential
     seconds_per_fade: f64,
+    fade_mode: FadeMode,         // Spherical and Space always fade by Age
+    segment_lifetime: f64,       // seconds before a segment has faded out completely in Age mode
+    segment_hold: f64,           // fraction of the lifetime at full brightness for HoldThenFade
+    max_history_segments: usize, // at most MAX_HISTORY_SEGMENTS as they're sent every frame
+    blend_mode: BlendMode,
+    light_exposure: f32, // how much light a line adds in Additive mode
+    background: Background,
+    symmetry: Symmetry,
+    geometry: Geometry,
+    spin: f64, // radians per second the globe or camera turns in Spherical and Space
+    tropism: [f64; 3], // the direction branches bend towards, down the screen is gravity
+    tropism_susceptibility: f64, // how strongly they bend, 0.0 for not at all
+
+    // params for colouring
+    palette: PaletteChoice,
+    colour_mode: ColourMode,
+    gradient_length: f64, // pixels travelled for one full cycle through the palette
+    gradient_depth: usize, // bracket depths for one full cycle through the palette
 }
 
 impl Parameters {
@@ -54,10 +126,57 @@ impl Parameters {
             max_rule_length: 10,
             lsystem_max_length: 2000,
             random_angle_chance: 0.5,
+            min_branch_width_ratio: 0.6,
+            max_branch_width_ratio: 1.0,
+            min_branch_length_ratio: 0.7,
+            max_branch_length_ratio: 1.0,
+            plant_parts: false,
+            turn_jitter: 0.0,
+            length_jitter: 0.0,
+            virtual_scale: 1.0,
+            camera_mode: CameraMode::Fixed,
+            infinite_zoom: None,
             distance_per_movement: 10.0,
             line_width: 0.75,
-            seconds_per_turtle_move: 0.04,
+            smooth_lines: false,
+            stroke_style: StrokeStyle::Solid,
+            min_pen_speed: 900.0,
+            max_pen_speed: 1500.0,
+            max_turtles: 3,
+            seconds_between_spawns: 4.0,
+            retire_policy: RetirePolicy::AfterSystems(3),
+            start_position: StartPosition::Continue,
+            drawing_order: DrawingOrder::Sequential,
+            max_growth_fronts: 32,
             seconds_per_fade: 0.04,
+            fade_mode: FadeMode::Global,
+            segment_lifetime: 20.0,
+            segment_hold: 0.5,
+            max_history_segments: 10000,
+            blend_mode: BlendMode::Alpha,
+            light_exposure: 1.5,
+            background: Background::Solid(graphics::color::BLACK),
+            symmetry: Symmetry::None,
+            geometry: Geometry::Flat(Topology::Torus),
+            spin: 0.1,
+            tropism: [0.0, 1.0, 0.0],
+            tropism_susceptibility: 0.0,
+            palette: PaletteChoice::Any,
+            colour_mode: ColourMode::Solid,
+            gradient_length: 2000.0,
+            gradient_depth: 6,
+        }
+    }
+
+    // The fade mode actually used. The spinning globe and the orbiting camera move
+    // everything already drawn so they always redraw from the history, a global fade would
+    // smear old lines instead.
+    fn effective_fade_mode(&self) -> FadeMode {
+        match (self.geometry, self.fade_mode) {
+            (Geometry::Spherical, FadeMode::Global) | (Geometry::Space, FadeMode::Global) => {
+                FadeMode::Age(DecayCurve::Linear)
+            }
+            (_, fade_mode) => fade_mode,
         }
     }
 }
@@ -69,50 +188,104 @@ pub struct TurtleStates {
 
 #[derive(Debug)]
 struct LSystem {
-    seed: String,
     string: String,
+    generations: Vec<u8>, // the growth cycle that produced each char in string
+    max_generation: u8,
     rules: HashMap<char, String>,
     angle: f64,
+    width_ratio: f64,  // line width multiplier applied at every '['
+    length_ratio: f64, // step length multiplier applied at every '['
+    jitter_seed: u64,  // makes the jitter of every turn and line the same whenever it's drawn
 }
 
 #[derive(Clone, Debug)]
 pub struct CurrentString {
     string: String,
+    generations: Vec<u8>,
+    max_generation: u8,
+    string_pos: usize, // where string starts in the whole lsystem string
+    string_len: usize, // length of the whole lsystem string
     angle: f64,
+    width_ratio: f64,
+    length_ratio: f64,
+    jitter_seed: u64,
 }
 
 #[derive(Clone)]
 pub struct TurtleState {
     pos: Position,
     colour: [f32; 4], // FIXME: should this be here??
+    palette: Palette,
+    distance_travelled: f64,
+    string_pos: usize, // index into the current substring of the next char to draw
+    segment_progress: f64, // how much of the F at string_pos has been drawn so far
     position_stack: Vec<Position>,
+    polygon_stack: Vec<Option<Vec<[f64; 3]>>>, // corners of each open '{', None once it wraps
+    pen: Pen,                                  // draws the lines instead when smooth_lines is on
+    stroke: Stroke,
+}
+
+// A single straight line drawn by the turtle
+#[derive(Copy, Clone)]
+pub struct Segment {
+    from: [f64; 3], // z is only used in the Space geometry
+    to: [f64; 3],
+    colour: [f32; 4],
+    width: f64,
+}
+
+// Everything the turtles have drawn since the last update
+pub struct Drawing {
+    segments: Vec<Segment>,
+    polygons: Vec<Polygon>,
 }
 
 #[derive(Copy, Clone)]
 struct Position {
     x: f64,
     y: f64,
+    z: f64,
     angle: f64,
+    frame: Frame, // which way the turtle faces in the Space geometry instead of angle
+    width_scale: f64,
+    length_scale: f64,
 }
 
-const FS_PER_TURTLE_MOVE: usize = 5; // number of lines to draw at once
 const MAX_GROWTH_CYCLES: usize = 200;
+
+// The canvas is a half float texture on the graphics card. Every side is kept within the
+// largest texture the card can hold, and the area to 8192 x 8192 which keeps it to half a
+// gigabyte.
+const MAX_CANVAS_PIXELS: f64 = 67_108_864.0;
+
+// The most virtual_scale the config file may ask for, on a small window the canvas limits
+// above may allow more than this but it's already too big a plane to see anything on
+const MAX_VIRTUAL_SCALE: f64 = 8.0;
+
+const FADE_ALPHA: f32 = 48.0 / 255.0; // how far towards the background every fade goes
 const MIN_ANGLE: f64 = 0.08726646;
 const MAX_ANGLE: f64 = 3.124139;
 const NON_RANDOM_ANGLES: [f64; 7] = [
-    0.3490659, 0.5235988, 0.6283185, 0.7853982, 1.047198, 1.570796, 2.356194,
+    0.3490659, FRAC_PI_6, 0.6283185, FRAC_PI_4, FRAC_PI_3, FRAC_PI_2, 2.356194,
 ];
 
 pub struct App {
     gl: GlGraphics,
-    current_strings: Vec<CurrentString>,
-    turtle_states: TurtleStates,
-    current_turtle_state: TurtleState,
-    next_turtle_state: TurtleState,
-    seconds_to_next_turtle_move: f64,
+    screen: Size,
+    draw_size: Size, // the screen in real pixels, which the canvas resolution is based on
+    world: Size,     // the plane the turtles draw on, the canvas covers all of it
+    camera: Camera,
+    canvas: Canvas,
+    batch: SegmentBatch, // segments waiting to be drawn this frame
+    batch_shader: BatchShader,
+    history: SegmentHistory,
+    turtles: Turtles,
+    infinite_zoom: Option<InfiniteZoom>, // draws instead of the turtles when there is one
     seconds_to_next_fade: f64,
-    should_fade: bool,
+    rotation: f64, // radians the globe or camera has turned in Spherical and Space
+    seconds: f64,  // since the screensaver started
     params: Parameters,
+    config: Config,
     rng: ThreadRng,
 }
 
@@ -124,8 +297,9 @@ fn main() {
 
     let opengl = OpenGL::V3_2;
 
-    // FIXME: get the full screen resolution automatically
-    let mut window: AppWindow = WindowSettings::new("Lsaver", (2560, 1440))
+    // The size here is only used if we are not fullscreen, glutin sizes a fullscreen
+    // window to the primary monitor's resolution so we query the real size afterwards
+    let mut window: AppWindow = WindowSettings::new("Lsaver", (1920, 1080))
         //.exit_on_esc(true)
         .graphics_api(opengl)
         .vsync(true)
@@ -135,19 +309,45 @@ fn main() {
         .unwrap();
 
     window.set_capture_cursor(true);
-    let params = Parameters::new();
+    let config = Config::new();
+    let params = config.load();
     let mut rng = rand::thread_rng();
+    let screen = window.size();
+    let draw_size = window.draw_size();
+    let world = world_size(screen, draw_size, &params);
+    let mut gl = GlGraphics::new(opengl);
+    let (canvas_width, canvas_height) = canvas_size(draw_size, &params);
+    let canvas = Canvas::new(
+        &mut gl,
+        world,
+        canvas_width,
+        canvas_height,
+        params.blend_mode,
+        params.light_exposure,
+        &params.background,
+    );
+
+    let infinite_zoom = params
+        .infinite_zoom
+        .map(|fractal| InfiniteZoom::new(fractal, &params, world, &mut rng));
 
     let mut app = App {
-        gl: GlGraphics::new(opengl),
-        current_strings: Vec::new(),
-        turtle_states: TurtleStates::new(&params, &mut rng),
-        current_turtle_state: TurtleState::new(&mut rng),
-        next_turtle_state: TurtleState::new(&mut rng),
-        seconds_to_next_turtle_move: 0.0,
+        gl,
+        screen,
+        draw_size,
+        world,
+        camera: Camera::new(world, screen),
+        canvas,
+        batch: SegmentBatch::new(),
+        batch_shader: BatchShader::new(),
+        history: SegmentHistory::new(),
+        turtles: Turtles::new(),
+        infinite_zoom,
         seconds_to_next_fade: 0.0,
-        should_fade: false,
+        rotation: 0.0,
+        seconds: 0.0,
         params,
+        config,
         rng,
     };
 
@@ -165,6 +365,10 @@ fn main() {
             app.update(&args);
         }
 
+        if let Some(args) = e.resize_args() {
+            app.resize(&args);
+        }
+
         // Exit when the user moves the mouse
         e.mouse_relative(|_| {
             mouse_move_count += 1;
@@ -174,111 +378,272 @@ fn main() {
         });
 
         // Any key exits
-        if let Some(_) = e.press_args() {
+        if e.press_args().is_some() {
             key_press_count += 1;
             if key_press_count > 1 {
                 std::process::exit(0);
             }
         }
     }
-
-    println!("ESC pressed");
 }
 
 impl App {
+    // The segments the turtle drew since the last frame are added to the canvas in one batch
+    // and then the canvas is copied to the screen. When fading by age the canvas is redrawn
+    // from the history instead, which lets the globe of the Spherical geometry turn with
+    // everything on it and the camera in Space orbit around what has been drawn.
     fn render(&mut self, args: &RenderArgs) {
-        use graphics::*;
+        if let Some(zoom) = &self.infinite_zoom {
+            // the whole figure moves every frame so it's drawn again from scratch, straight
+            // to the screen over the background on the canvas
+            self.batch.clear();
+            zoom.draw(self.world, &self.params, &mut self.batch);
+        } else {
+            if let FadeMode::Age(curve) = self.params.effective_fade_mode() {
+                self.batch.clear();
+                let drawing = self.history.faded(curve, &self.params);
+                let drawing = project(drawing, self.params.geometry, self.world, self.rotation);
+                if self.params.geometry == Geometry::Spherical {
+                    // copies of the globe's map would be nonsense, the globe itself is copied
+                    self.batch
+                        .extend(&replicate(drawing, self.params.symmetry, self.world));
+                } else {
+                    self.batch.extend(&drawing);
+                }
+                self.canvas.clear(&mut self.gl);
+            }
 
-        let current_strings = &self.current_strings;
+            if !self.batch.is_empty() {
+                self.canvas
+                    .draw(&mut self.gl, &self.batch_shader, &self.batch);
+                self.batch.clear();
+            }
+        }
 
-        // Since we mutate the turtle as we draw it we need to clone it beforehand as we
-        // may be drawing the same turtle over and over again
-        self.next_turtle_state = self.current_turtle_state.clone();
+        let (screen, camera, canvas) = (self.screen, &self.camera, &self.canvas);
+        let (batch, batch_shader) = (&self.batch, &self.batch_shader);
+        self.gl.draw(args.viewport(), move |c, gl| {
+            let view = camera.view(c.transform, screen);
+            canvas.show(gl, view);
 
-        let state = &mut self.next_turtle_state;
-        let params = &self.params;
+            // only the infinite zoom leaves anything in the batch by now
+            batch.submit(batch_shader, view, BlendMode::Alpha, gl);
+        });
+        self.batch.clear();
+    }
 
-        // toggle line fading
-        let should_fade = self.should_fade;
-        if should_fade {
-            self.should_fade = false;
+    // Everything sized to the window is built again at the new size
+    fn resize(&mut self, args: &ResizeArgs) {
+        // a minimised window reports a zero size which would leave nowhere to draw
+        if args.window_size[0] < 1.0 || args.window_size[1] < 1.0 {
+            return;
         }
 
-        self.gl.draw(args.viewport(), move |c, gl| {
-            if should_fade {
-                // Draw a filled semi-transparrent rectangle over everything to give the appearance that
-                // everything is fading out to black
-                rectangle(
-                    color::hex("00000030"),
-                    [0.0, 0.0, WINDOW_WIDTH, WINDOW_HEIGHT],
-                    c.transform,
-                    gl,
-                );
-            }
+        self.screen = args.window_size.into();
+        self.draw_size = args.draw_size.into();
+        self.rebuild();
+    }
 
-            for curent_string in current_strings {
-                draw_lsystem_substring(
-                    &curent_string.string,
-                    curent_string.angle,
-                    state,
-                    params,
-                    c,
-                    gl,
-                );
-            }
-        });
+    // Uses settings edited in the config file. The turtles pick up the new settings at the
+    // start of their next lsystem but anything the canvas was built from needs a new one,
+    // and turtles in another geometry have to start again.
+    fn reconfigure(&mut self, params: Parameters) {
+        let new_geometry = params.geometry != self.params.geometry;
+        let new_canvas = new_geometry
+            || params.virtual_scale != self.params.virtual_scale
+            || params.blend_mode != self.params.blend_mode
+            || params.light_exposure != self.params.light_exposure
+            || params.background != self.params.background
+            || params.infinite_zoom != self.params.infinite_zoom;
+
+        self.params = params;
+        if new_geometry {
+            self.turtles = Turtles::new();
+            self.history = SegmentHistory::new();
+        }
+
+        if new_canvas {
+            self.rebuild();
+        }
+    }
+
+    // The plane is a torus so the turtles are wrapped back onto it when it changes size
+    fn rebuild(&mut self) {
+        self.world = world_size(self.screen, self.draw_size, &self.params);
+        self.camera = Camera::new(self.world, self.screen);
+        self.turtles.wrap_to(self.world);
+        self.infinite_zoom = match self.params.infinite_zoom {
+            Some(fractal) => Some(InfiniteZoom::new(
+                fractal,
+                &self.params,
+                self.world,
+                &mut self.rng,
+            )),
+            None => None,
+        };
+
+        // start again with a blank canvas at the new resolution
+        self.batch.clear();
+        let (canvas_width, canvas_height) = canvas_size(self.draw_size, &self.params);
+        self.canvas = Canvas::new(
+            &mut self.gl,
+            self.world,
+            canvas_width,
+            canvas_height,
+            self.params.blend_mode,
+            self.params.light_exposure,
+            &self.params.background,
+        );
     }
 
     // Updates what to draw according to how the clock has progresses
     // This disconnects the draw speed from the frame rate
     fn update(&mut self, args: &UpdateArgs) {
-        self.seconds_to_next_turtle_move -= args.dt;
+        if let Some(params) = self.config.changed() {
+            self.reconfigure(params);
+        }
+
         self.seconds_to_next_fade -= args.dt;
+        self.history.advance(args.dt, &self.params);
+        self.rotation += self.params.spin * args.dt;
+        self.seconds += args.dt;
 
-        // We fetch 5 or so turtle lines at a time and this controlls the rate so therefore
-        // the speed at which we draw lines
-        if self.seconds_to_next_turtle_move <= 0.0 {
-            let current_strings: Vec<CurrentString> = loop {
-                if let Some(cs) = self.turtle_states.next() {
-                    break cs;
-                } else {
-                    // When we come to the end of our current turtle we candomly generate another one
-                    // can change the pen colour
-                    self.turtle_states = TurtleStates::new(&self.params, &mut self.rng);
-                    self.next_turtle_state.colour = rand_colour(&mut self.rng);
-                }
-            };
+        if self.params.background.is_changing() {
+            self.canvas
+                .set_background(&self.params.background, self.seconds);
+        }
 
-            self.current_strings = current_strings;
-            self.current_turtle_state = self.next_turtle_state.clone();
-            self.seconds_to_next_turtle_move = self.params.seconds_per_turtle_move;
+        if let Some(zoom) = self.infinite_zoom.as_mut() {
+            zoom.update(args.dt, self.world, &mut self.rng);
+            return;
         }
 
         // This controlls the fadeout of the lines
-        if self.seconds_to_next_fade <= 0.0 {
+        let global_fade = matches!(self.params.effective_fade_mode(), FadeMode::Global);
+        if global_fade && self.seconds_to_next_fade <= 0.0 {
+            // Blend a little of the background over everything to give the appearance that
+            // everything is fading out into it
             self.seconds_to_next_fade = self.params.seconds_per_fade;
-            self.should_fade = true;
+            self.canvas.fade(&mut self.gl, FADE_ALPHA);
         }
+
+        let mut drawing = Drawing::new();
+        self.turtles.update(
+            args.dt,
+            &self.params,
+            self.world,
+            &self.canvas,
+            &mut self.rng,
+            &mut drawing,
+        );
+        // the camera follows the lead turtle where it's seen on the globe or in space
+        let (geometry, rotation) = (self.params.geometry, self.rotation);
+        let lead = self.turtles.lead().and_then(|position| {
+            let [x, y, _] = project_point(position, geometry, self.world, rotation)?;
+            Some([x, y])
+        });
+        self.camera.update(
+            args.dt,
+            self.params.camera_mode,
+            self.world,
+            self.screen,
+            lead,
+            &mut self.rng,
+        );
+        // the globe is copied once it's been projected onto the screen
+        let drawing = match self.params.geometry {
+            Geometry::Spherical => drawing,
+            _ => replicate(drawing, self.params.symmetry, self.world),
+        };
+
+        match self.params.effective_fade_mode() {
+            FadeMode::Global => {
+                // Each segment is drawn exactly once, the canvas keeps it from then on
+                self.batch.extend(&project(
+                    drawing,
+                    self.params.geometry,
+                    self.world,
+                    self.rotation,
+                ));
+            }
+            FadeMode::Age(_) => self.history.push(drawing, &self.params),
+        }
+    }
+}
+
+// The plane the turtles draw on is virtual_scale screens wide and high
+fn world_size(screen: Size, draw_size: Size, params: &Parameters) -> Size {
+    let scale = virtual_scale(draw_size, params);
+    Size {
+        width: screen.width * scale,
+        height: screen.height * scale,
     }
 }
 
-fn rand_colour(rng: &mut ThreadRng) -> [f32; 4] {
-    let red = rng.gen_range(0.5, 1.0);
-    let green = rng.gen_range(0.5, 1.0);
-    let blue = rng.gen_range(0.5, 1.0);
-    [red, green, blue, 1.0]
+// The canvas covers the whole plane at the screen's real resolution
+fn canvas_size(draw_size: Size, params: &Parameters) -> (u32, u32) {
+    let scale = virtual_scale(draw_size, params);
+    (
+        (draw_size.width * scale) as u32,
+        (draw_size.height * scale) as u32,
+    )
+}
+
+// virtual_scale cut down so the canvas for a screen of draw_size real pixels stays within
+// the largest texture and MAX_CANVAS_PIXELS. A screen that is already bigger than that just
+// gets a plane the size of the screen.
+fn virtual_scale(draw_size: Size, params: &Parameters) -> f64 {
+    let widest = draw_size.width.max(draw_size.height);
+    let largest = (max_texture_side() / widest)
+        .min((MAX_CANVAS_PIXELS / (draw_size.width * draw_size.height)).sqrt());
+    params.virtual_scale.min(largest).max(1.0)
+}
+
+impl Drawing {
+    fn new() -> Self {
+        Drawing {
+            segments: Vec::new(),
+            polygons: Vec::new(),
+        }
+    }
 }
 
 impl TurtleState {
-    fn new(rng: &mut ThreadRng) -> Self {
+    fn new(params: &Parameters, rng: &mut ThreadRng) -> Self {
+        let palette = Palette::new(params.palette, rng);
+        let pos = Position {
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+            angle: 0.0,
+            frame: Frame::flat(0.0),
+            width_scale: 1.0,
+            length_scale: 1.0,
+        };
         TurtleState {
-            pos: Position {
-                x: 0.0,
-                y: 0.0,
-                angle: 0.0,
-            },
-            colour: rand_colour(rng),
+            pos,
+            colour: palette.pick(rng),
+            palette,
+            distance_travelled: 0.0,
+            string_pos: 0,
+            segment_progress: 0.0,
             position_stack: Vec::new(),
+            polygon_stack: Vec::new(),
+            pen: Pen::new(pos),
+            stroke: Stroke::new(params.stroke_style, rng),
+        }
+    }
+
+    fn wrap_to(&mut self, screen: Size) {
+        let pen = [&mut self.pen.pos, &mut self.pen.target];
+        for pos in self
+            .position_stack
+            .iter_mut()
+            .chain(Some(&mut self.pos))
+            .chain(pen)
+        {
+            pos.x = pos.x.rem_euclid(screen.width);
+            pos.y = pos.y.rem_euclid(screen.height);
         }
     }
 }
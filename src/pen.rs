@@ -0,0 +1,310 @@
+use std::collections::VecDeque;
+use std::f64::consts::{PI, TAU};
+
+use super::*;
+
+// How many straight pieces the pen rounds off a corner with when smooth_lines is on
+const CORNER_PIECES: usize = 8;
+
+// Draws a smoothed copy of the turtle's path. Within a run of F's the pen lags half an F
+// behind the turtle so it can round off each corner before drawing on to the middle of
+// the next F.
+#[derive(Clone)]
+pub struct Pen {
+    pub pos: Position,
+    pub target: Position, // where the pen ends up once the current F is drawn
+    path: VecDeque<Step>, // what's left to draw of the current F
+    speed: f64,           // how far the pen moves for every pixel the turtle moves
+    axis: [f64; 3],       // what the 3D pen turns about at the current corner
+    turned_from: Option<Position>, // the turtle at the end of the last F of the current run
+    lag: f64,             // how far behind the turtle the pen is between F's
+    colour: [f32; 4],
+    width: f64,
+}
+
+// A piece of the pen's path, a turn followed by a straight line
+#[derive(Copy, Clone)]
+struct Step {
+    turn: f64,
+    length: f64,
+}
+
+impl Pen {
+    pub fn new(pos: Position) -> Self {
+        Pen {
+            pos,
+            target: pos,
+            path: VecDeque::new(),
+            speed: 1.0,
+            axis: [0.0, 0.0, 1.0],
+            turned_from: None,
+            lag: 0.0,
+            colour: [0.0; 4],
+            width: 0.0,
+        }
+    }
+
+    // Works out what to draw while the turtle draws the F of length that starts at pos.
+    // The first F of a run is drawn straight to its middle, after that the pen curves from
+    // the middle of the last F to the middle of this one.
+    pub fn start(
+        &mut self,
+        pos: &Position,
+        length: f64,
+        width: f64,
+        colour: [f32; 4],
+        geometry: Geometry,
+        screen: Size,
+    ) {
+        let half = length / 2.0;
+        self.target = *pos;
+        move_forward(
+            &mut self.target,
+            half,
+            width,
+            colour,
+            geometry,
+            screen,
+            &mut Vec::new(),
+        );
+
+        self.path = match self.turned_from {
+            Some(turned_from) => self.corner(&turned_from, pos, half, geometry),
+            None => {
+                self.pos = *pos;
+                VecDeque::from(vec![Step {
+                    turn: 0.0,
+                    length: half,
+                }])
+            }
+        };
+
+        let path_length: f64 = self.path.iter().map(|step| step.length).sum();
+        self.speed = path_length / length;
+        self.lag = half;
+        self.colour = colour;
+        self.width = width;
+    }
+
+    // A quadratic Bezier curve from lag before the corner to half after it with the corner
+    // as its control point. The pen follows it as a list of turns and straight lines so it
+    // bends the same way in every geometry.
+    fn corner(
+        &mut self,
+        turned_from: &Position,
+        pos: &Position,
+        half: f64,
+        geometry: Geometry,
+    ) -> VecDeque<Step> {
+        let angle = match geometry {
+            Geometry::Space => {
+                let (from, to) = (turned_from.frame.heading, pos.frame.heading);
+                let axis = cross(from, to);
+                let sin = dot(axis, axis).sqrt();
+
+                // turning straight back has no axis but any at right angles will do
+                self.axis = if sin > 1e-9 {
+                    scale(axis, 1.0 / sin)
+                } else {
+                    pos.frame.up
+                };
+                sin.atan2(dot(from, to))
+            }
+            _ => (pos.angle - turned_from.angle + PI).rem_euclid(TAU) - PI,
+        };
+
+        // u is along the last F and v is the way the turtle turned with the corner at 0, 0
+        let (sin, cos) = angle.sin_cos();
+        let lag = self.lag;
+        let point = |t: f64| {
+            let (before, after) = ((1.0 - t) * (1.0 - t), t * t);
+            [half * cos * after - lag * before, half * sin * after]
+        };
+
+        let mut path = VecDeque::new();
+        let mut heading = 0.0;
+        for piece in 0..CORNER_PIECES {
+            let from = point(piece as f64 / CORNER_PIECES as f64);
+            let to = point((piece + 1) as f64 / CORNER_PIECES as f64);
+            let (u, v) = (to[0] - from[0], to[1] - from[1]);
+            let direction = v.atan2(u);
+            path.push_back(Step {
+                turn: direction - heading,
+                length: u.hypot(v),
+            });
+            heading = direction;
+        }
+
+        // end up facing exactly along the new F
+        path.push_back(Step {
+            turn: angle - heading,
+            length: 0.0,
+        });
+        path
+    }
+
+    // Moves the pen along its path as the turtle moves distance along the F
+    pub fn draw(
+        &mut self,
+        distance: f64,
+        geometry: Geometry,
+        screen: Size,
+        segments: &mut Vec<Segment>,
+    ) {
+        self.walk(distance * self.speed, geometry, screen, segments);
+    }
+
+    // Draws the rest of the path now the turtle has reached pos at the end of the F
+    pub fn finish(
+        &mut self,
+        pos: &Position,
+        geometry: Geometry,
+        screen: Size,
+        segments: &mut Vec<Segment>,
+    ) {
+        self.walk(f64::INFINITY, geometry, screen, segments);
+
+        // turns don't quite add up the same way in curved geometries so rather than drift
+        // away from the turtle the pen is put back on its path
+        self.pos = self.target;
+        self.turned_from = Some(*pos);
+    }
+
+    // Ends the run by drawing straight on to where the turtle finished the last F
+    pub fn lift(&mut self, geometry: Geometry, screen: Size, segments: &mut Vec<Segment>) {
+        if self.turned_from.take().is_some() {
+            let (width, colour) = (self.width, self.colour);
+            move_forward(
+                &mut self.pos,
+                self.lag,
+                width,
+                colour,
+                geometry,
+                screen,
+                segments,
+            );
+        }
+    }
+
+    fn walk(
+        &mut self,
+        distance: f64,
+        geometry: Geometry,
+        screen: Size,
+        segments: &mut Vec<Segment>,
+    ) {
+        let mut distance = distance;
+        while let Some(mut step) = self.path.pop_front() {
+            match geometry {
+                Geometry::Space => self.pos.frame.rotate(self.axis, step.turn),
+                _ => self.pos.angle += step.turn,
+            }
+
+            let length = step.length.min(distance);
+            if length > 0.0 {
+                let (width, colour) = (self.width, self.colour);
+                move_forward(
+                    &mut self.pos,
+                    length,
+                    width,
+                    colour,
+                    geometry,
+                    screen,
+                    segments,
+                );
+            }
+
+            distance -= length;
+            if length < step.length {
+                // carry on along this line next time
+                step.turn = 0.0;
+                step.length -= length;
+                self.path.push_front(step);
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCREEN: Size = Size {
+        width: 200.0,
+        height: 200.0,
+    };
+
+    // The segments drawn for the whole of string from 50, 50 facing along x
+    fn draw(string: &str, angle: f64, smooth_lines: bool) -> Vec<Segment> {
+        let params = Parameters {
+            smooth_lines,
+            ..Parameters::new()
+        };
+        let current_string = CurrentString {
+            string: string.to_string(),
+            generations: vec![0; string.len()],
+            max_generation: 0,
+            string_pos: 0,
+            string_len: string.len(),
+            angle,
+            width_ratio: 1.0,
+            length_ratio: 1.0,
+            jitter_seed: 0,
+        };
+
+        let mut turtle_state = TurtleState::new(&params, &mut thread_rng());
+        turtle_state.pos.x = 50.0;
+        turtle_state.pos.y = 50.0;
+        let mut drawing = Drawing::new();
+        let mut distance = f64::INFINITY;
+        draw_lsystem_substring(
+            &current_string,
+            &mut turtle_state,
+            &params,
+            SCREEN,
+            &mut distance,
+            &mut drawing,
+            &mut Vec::new(),
+        );
+        drawing.segments
+    }
+
+    fn along(segment: &Segment, axis: usize, at: f64) -> bool {
+        (segment.from[axis] - at).abs() < 1e-9 && (segment.to[axis] - at).abs() < 1e-9
+    }
+
+    #[test]
+    fn straight_runs_are_unchanged() {
+        let smooth = draw("FFF", FRAC_PI_2, true);
+        assert!(smooth.iter().all(|segment| along(segment, 1, 50.0)));
+
+        let length: f64 = smooth
+            .iter()
+            .map(|segment| segment.to[0] - segment.from[0])
+            .sum();
+        let start = smooth.first().unwrap().from[0];
+        let end = smooth.last().unwrap().to[0];
+        assert!(
+            (length - 30.0).abs() < 1e-9,
+            "drew {} instead of 30",
+            length
+        );
+        assert!((start - 50.0).abs() < 1e-9 && (end - 80.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn corners_are_not_rounded_across_brackets() {
+        // the same turn is rounded off when it's within one run of F's
+        let rounded = draw("F+F", FRAC_PI_2, true);
+        assert!(rounded
+            .iter()
+            .any(|segment| !along(segment, 1, 50.0) && !along(segment, 0, 60.0)));
+
+        // but the run ends at '[' and ']' so the branch leaves from the corner itself
+        let branched = draw("F[+F]F", FRAC_PI_2, true);
+        assert!(branched
+            .iter()
+            .all(|segment| along(segment, 1, 50.0) || along(segment, 0, 60.0)));
+    }
+}
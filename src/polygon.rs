@@ -0,0 +1,186 @@
+use std::rc::Rc;
+
+// A filled shape drawn by the turtle, either between '{' and '}' or a glyph. It's cut into
+// triangles once when it's made, projecting or copying it only moves the corners.
+#[derive(Clone)]
+pub struct Polygon {
+    pub points: Vec<[f64; 3]>,
+    pub triangles: Rc<[[usize; 3]]>, // indices into points that fill the outline
+    pub colour: [f32; 4],
+}
+
+impl Polygon {
+    // Fills the outline by ear clipping, cutting off one corner at a time whose triangle has
+    // nothing else inside it. This fills any outline that doesn't cross itself exactly, which
+    // is what leaves and petals are. Turtle paths can cross themselves and then there may be
+    // no such corner left, from then on corners are cut off in order without looking so
+    // whatever the outline goes round is still roughly filled.
+    pub fn new(points: Vec<[f64; 3]>, colour: [f32; 4]) -> Self {
+        Polygon {
+            triangles: triangulate(&points).into(),
+            points,
+            colour,
+        }
+    }
+
+    // The same polygon with its corners moved somewhere else
+    pub fn with_points(&self, points: Vec<[f64; 3]>) -> Self {
+        Polygon {
+            points,
+            triangles: Rc::clone(&self.triangles),
+            colour: self.colour,
+        }
+    }
+}
+
+fn triangulate(points: &[[f64; 3]]) -> Vec<[usize; 3]> {
+    // an outline in 3D is flattened onto whichever plane it faces most
+    let flat = flatten(points);
+
+    let mut outline: Vec<usize> = (0..points.len()).collect();
+    outline.dedup_by(|&mut b, &mut a| flat[a] == flat[b]);
+    if outline.len() > 1 && flat[outline[0]] == flat[outline[outline.len() - 1]] {
+        outline.pop();
+    }
+
+    // which way round the outline goes decides which corners point outwards
+    let corners: Vec<[f64; 2]> = outline.iter().map(|&i| flat[i]).collect();
+    let winding = area(&corners).signum();
+    if outline.len() < 3 || winding == 0.0 {
+        return Vec::new();
+    }
+
+    let mut triangles = Vec::with_capacity(outline.len() - 2);
+    let mut remaining = outline;
+    let mut corner = 0;
+    let mut crossed = false;
+    while remaining.len() > 3 {
+        let count = remaining.len();
+        let ear = (0..count)
+            .map(|offset| (corner + offset) % count)
+            .take(if crossed { 0 } else { count })
+            .find(|&i| is_ear(&flat, &remaining, i, winding));
+        crossed = ear.is_none();
+        let ear = ear.unwrap_or(corner % count);
+
+        triangles.push(triangle_at(&remaining, ear));
+        remaining.remove(ear);
+        corner = ear;
+    }
+
+    triangles.push(triangle_at(&remaining, 1));
+    triangles
+}
+
+// The points on the plane that the polygon faces most, which is the plane of the screen
+// for everything but the Space geometry. The plane is picked by the Newell normal.
+fn flatten(points: &[[f64; 3]]) -> Vec<[f64; 2]> {
+    let mut normal = [0.0; 3];
+    for (i, a) in points.iter().enumerate() {
+        let b = points[(i + 1) % points.len()];
+        normal[0] += (a[1] - b[1]) * (a[2] + b[2]);
+        normal[1] += (a[2] - b[2]) * (a[0] + b[0]);
+        normal[2] += (a[0] - b[0]) * (a[1] + b[1]);
+    }
+
+    let (x, y) = if normal[2].abs() >= normal[0].abs() && normal[2].abs() >= normal[1].abs() {
+        (0, 1)
+    } else if normal[0].abs() >= normal[1].abs() {
+        (1, 2)
+    } else {
+        (2, 0)
+    };
+
+    points.iter().map(|p| [p[x], p[y]]).collect()
+}
+
+// The corner of the remaining outline at index i with its neighbours either side
+fn triangle_at(remaining: &[usize], i: usize) -> [usize; 3] {
+    let count = remaining.len();
+    [
+        remaining[(i + count - 1) % count],
+        remaining[i],
+        remaining[(i + 1) % count],
+    ]
+}
+
+// Whether the corner at index i points outwards with no other corner inside its triangle
+fn is_ear(points: &[[f64; 2]], remaining: &[usize], i: usize, winding: f64) -> bool {
+    let [a, b, c] = triangle_at(remaining, i).map(|index| points[index]);
+    if turn(a, b, c) * winding <= 0.0 {
+        return false;
+    }
+
+    remaining.iter().map(|&index| points[index]).all(|p| {
+        p == a
+            || p == b
+            || p == c
+            || turn(a, b, p) * winding < 0.0
+            || turn(b, c, p) * winding < 0.0
+            || turn(c, a, p) * winding < 0.0
+    })
+}
+
+// Positive when a, b, c turn anticlockwise in maths coordinates
+fn turn(a: [f64; 2], b: [f64; 2], c: [f64; 2]) -> f64 {
+    (b[0] - a[0]) * (c[1] - a[1]) - (b[1] - a[1]) * (c[0] - a[0])
+}
+
+// The signed area of the outline, positive when it goes anticlockwise in maths coordinates
+pub fn area(points: &[[f64; 2]]) -> f64 {
+    let mut area = 0.0;
+    for (i, a) in points.iter().enumerate() {
+        let b = points[(i + 1) % points.len()];
+        area += a[0] * b[1] - b[0] * a[1];
+    }
+
+    area / 2.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // an L shape whose fan from the first corner would spill outside it
+    const L_SHAPE: [[f64; 2]; 6] = [
+        [0.0, 0.0],
+        [4.0, 0.0],
+        [4.0, 1.0],
+        [1.0, 1.0],
+        [1.0, 4.0],
+        [0.0, 4.0],
+    ];
+
+    fn filled(polygon: &Polygon, x: usize, y: usize) -> f64 {
+        polygon
+            .triangles
+            .iter()
+            .map(|triangle| area(&triangle.map(|i| [polygon.points[i][x], polygon.points[i][y]])))
+            .map(f64::abs)
+            .sum()
+    }
+
+    #[test]
+    fn concave_polygons_are_filled_exactly() {
+        let points = L_SHAPE.iter().map(|p| [p[0], p[1], 0.0]).collect();
+        let polygon = Polygon::new(points, [1.0; 4]);
+
+        let filled = filled(&polygon, 0, 1);
+        assert_eq!(polygon.triangles.len(), 4);
+        assert!(
+            (filled - 7.0).abs() < 1e-9,
+            "filled {} instead of 7",
+            filled
+        );
+    }
+
+    #[test]
+    fn polygons_facing_sideways_in_space_are_filled() {
+        // the L shape stood up on its edge so it's a line when seen from the front
+        let points = L_SHAPE.iter().map(|p| [p[0], 0.0, p[1]]).collect();
+        let polygon = Polygon::new(points, [1.0; 4]);
+
+        assert_eq!(polygon.triangles.len(), 4);
+        assert!((filled(&polygon, 0, 2) - 7.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,283 @@
+use gl::types::GLuint;
+use graphics::math::Matrix2d;
+use graphics::triangulation::{tx, ty};
+use opengl_graphics::shader_utils::DynamicAttribute;
+use opengl_graphics::GlGraphics;
+
+use super::*;
+
+// Lines and the outlines of polygons fade out over this many units of the plane either side
+// of their edges, which anti-aliases them about as well as a pixel wide blur
+const FEATHER: f64 = 0.5;
+
+const BATCH_VERTEX_SHADER: &str = "
+#version 150 core
+in vec2 pos;
+in vec4 colour;
+out vec4 v_colour;
+
+void main() {
+    v_colour = colour;
+    gl_Position = vec4(pos, 0.0, 1.0);
+}
+";
+
+const BATCH_FRAGMENT_SHADER: &str = "
+#version 150 core
+in vec4 v_colour;
+out vec4 o_colour;
+
+void main() {
+    o_colour = v_colour;
+}
+";
+
+// A vertex buffer of segments and polygons tessellated into triangles, each vertex with its
+// own colour in linear light. Segments are collected here over a frame and drawn together
+// at the end of it in a single draw call. Triangles are drawn in the order they were added
+// so shapes that overlap still blend over each other as if drawn one by one.
+pub struct SegmentBatch {
+    vertices: Vec<[f64; 2]>, // in plane coordinates, three per triangle
+    colours: Vec<[f32; 4]>,  // one per vertex
+}
+
+impl SegmentBatch {
+    pub fn new() -> Self {
+        SegmentBatch {
+            vertices: Vec::new(),
+            colours: Vec::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vertices.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+        self.colours.clear();
+    }
+
+    // Adds a segment as a rectangle of the segment's width either side of the line, the
+    // same shape that graphics::line_from_to draws. The sides fade out so the rectangle is a
+    // solid core between two feathered edges that hold as much colour as the sides they
+    // replace. Lines too thin for a solid core are fainter instead.
+    pub fn push(&mut self, segment: &Segment) {
+        let dx = segment.to[0] - segment.from[0];
+        let dy = segment.to[1] - segment.from[1];
+        let length = (dx * dx + dy * dy).sqrt();
+        if length == 0.0 || segment.width <= 0.0 {
+            return;
+        }
+
+        let core = (segment.width - FEATHER).max(0.0);
+        let outer = segment.width + FEATHER;
+        let colour = linear(segment.colour);
+        let solid = [
+            colour[0],
+            colour[1],
+            colour[2],
+            colour[3] * (2.0 * segment.width / (core + outer)) as f32,
+        ];
+        let clear = [colour[0], colour[1], colour[2], 0.0];
+
+        // the corners of the rectangle at distance across the line
+        let across = |distance: f64| {
+            let nx = -dy / length * distance;
+            let ny = dx / length * distance;
+            (
+                [segment.from[0] + nx, segment.from[1] + ny],
+                [segment.to[0] + nx, segment.to[1] + ny],
+            )
+        };
+
+        let mut strips = vec![(across(outer), clear, across(core), solid)];
+        if core > 0.0 {
+            strips.push((across(core), solid, across(-core), solid));
+        }
+        strips.push((across(-core), solid, across(-outer), clear));
+
+        for ((a, b), near, (d, c), far) in strips {
+            self.push_triangle([a, b, c], [near, near, far]);
+            self.push_triangle([a, c, d], [near, far, far]);
+        }
+    }
+
+    // Adds a polygon's triangles with its outline feathered outwards to anti-alias it
+    pub fn push_polygon(&mut self, polygon: &Polygon) {
+        let points: Vec<[f64; 2]> = polygon.points.iter().map(|p| [p[0], p[1]]).collect();
+        let colour = linear(polygon.colour);
+        for triangle in polygon.triangles.iter() {
+            self.push_triangle(triangle.map(|i| points[i]), [colour; 3]);
+        }
+
+        // which way round the outline goes decides which way is outwards
+        let winding = area(&points).signum();
+        let clear = [colour[0], colour[1], colour[2], 0.0];
+        for (i, &a) in points.iter().enumerate() {
+            let b = points[(i + 1) % points.len()];
+            let length = ((b[0] - a[0]).powi(2) + (b[1] - a[1]).powi(2)).sqrt();
+            if length == 0.0 || polygon.triangles.is_empty() {
+                continue;
+            }
+
+            // outwards is to the right of an anticlockwise outline
+            let nx = (b[1] - a[1]) / length * FEATHER * 2.0 * winding;
+            let ny = -(b[0] - a[0]) / length * FEATHER * 2.0 * winding;
+            let c = [b[0] + nx, b[1] + ny];
+            let d = [a[0] + nx, a[1] + ny];
+            self.push_triangle([a, b, c], [colour, colour, clear]);
+            self.push_triangle([a, c, d], [colour, clear, clear]);
+        }
+    }
+
+    pub fn extend(&mut self, drawing: &Drawing) {
+        for segment in drawing.segments.iter() {
+            self.push(segment);
+        }
+
+        for polygon in drawing.polygons.iter() {
+            self.push_polygon(polygon);
+        }
+    }
+
+    fn push_triangle(&mut self, triangle: [[f64; 2]; 3], colours: [[f32; 4]; 3]) {
+        self.vertices.extend_from_slice(&triangle);
+        self.colours.extend_from_slice(&colours);
+    }
+
+    // The middle of every triangle and its area weighted by how opaque it is
+    pub fn coverage(&self) -> impl Iterator<Item = ([f64; 2], f64)> + '_ {
+        self.vertices
+            .chunks_exact(3)
+            .zip(self.colours.chunks_exact(3))
+            .map(|(triangle, colours)| {
+                let centre = [
+                    (triangle[0][0] + triangle[1][0] + triangle[2][0]) / 3.0,
+                    (triangle[0][1] + triangle[1][1] + triangle[2][1]) / 3.0,
+                ];
+                let alpha = (colours[0][3] + colours[1][3] + colours[2][3]) / 3.0;
+                (centre, area(triangle).abs() * alpha as f64)
+            })
+    }
+
+    // Draws every triangle in one go with transform taking the plane to normalised device
+    // coordinates. Must be called with the framebuffer to draw on bound and its viewport set.
+    pub fn submit(
+        &self,
+        shader: &BatchShader,
+        transform: Matrix2d,
+        blend_mode: BlendMode,
+        graphics: &mut GlGraphics,
+    ) {
+        if self.is_empty() {
+            return;
+        }
+
+        let positions: Vec<[f32; 2]> = self
+            .vertices
+            .iter()
+            .map(|v| [tx(transform, v[0], v[1]), ty(transform, v[0], v[1])])
+            .collect();
+
+        unsafe {
+            gl::UseProgram(shader.program);
+            gl::BindVertexArray(shader.vao);
+            gl::Enable(gl::BLEND);
+            gl::BlendEquation(gl::FUNC_ADD);
+            match blend_mode {
+                BlendMode::Alpha => gl::BlendFuncSeparate(
+                    gl::SRC_ALPHA,
+                    gl::ONE_MINUS_SRC_ALPHA,
+                    gl::ONE,
+                    gl::ONE_MINUS_SRC_ALPHA,
+                ),
+                BlendMode::Additive => {
+                    gl::BlendFuncSeparate(gl::SRC_ALPHA, gl::ONE, gl::ZERO, gl::ONE)
+                }
+            }
+
+            gl::Disable(gl::CULL_FACE);
+            shader.pos.set(&positions);
+            shader.colour.set(&self.colours);
+            gl::DrawArrays(gl::TRIANGLES, 0, positions.len() as i32);
+            gl::BindVertexArray(0);
+        }
+
+        // GlGraphics has to set up its own program and blending again
+        graphics.clear_program();
+        graphics.clear_draw_state();
+    }
+}
+
+// The shader program every batch is drawn with
+pub struct BatchShader {
+    program: GLuint,
+    vao: GLuint,
+    pos: DynamicAttribute,
+    colour: DynamicAttribute,
+}
+
+impl BatchShader {
+    pub fn new() -> Self {
+        let program = link_program(BATCH_VERTEX_SHADER, BATCH_FRAGMENT_SHADER);
+        let mut vao = 0;
+        unsafe {
+            gl::GenVertexArrays(1, &mut vao);
+        }
+
+        BatchShader {
+            program,
+            vao,
+            pos: DynamicAttribute::xy(program, "pos", vao).unwrap(),
+            colour: DynamicAttribute::rgba(program, "colour", vao).unwrap(),
+        }
+    }
+}
+
+impl Drop for BatchShader {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteVertexArrays(1, &self.vao);
+            gl::DeleteProgram(self.program);
+        }
+    }
+}
+
+// The colour with its red, green and blue in linear light for blending
+fn linear(colour: [f32; 4]) -> [f32; 4] {
+    [
+        srgb_to_linear(colour[0] as f64) as f32,
+        srgb_to_linear(colour[1] as f64) as f32,
+        srgb_to_linear(colour[2] as f64) as f32,
+        colour[3],
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn feathered_segments_hold_as_much_colour_as_solid_ones() {
+        for &width in &[0.2, 0.5, 3.0] {
+            let mut batch = SegmentBatch::new();
+            batch.push(&Segment {
+                from: [1.0, 1.0, 0.0],
+                to: [11.0, 1.0, 0.0],
+                colour: [1.0, 1.0, 1.0, 0.5],
+                width,
+            });
+
+            let covered: f64 = batch.coverage().map(|(_, amount)| amount).sum();
+            let solid = 2.0 * width * 10.0 * 0.5;
+            assert!(
+                (covered - solid).abs() < 1e-6,
+                "a line {} wide covered {} instead of {}",
+                width,
+                covered,
+                solid
+            );
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use serde::Deserialize;
+use std::collections::VecDeque;
+
+use super::*;
+
+// How quickly a segment fades out over its lifetime
+#[derive(Copy, Clone, Debug, Deserialize)]
+pub enum DecayCurve {
+    Linear,
+    Exponential,  // drops off quickly at first and then lingers
+    HoldThenFade, // full brightness for segment_hold of the lifetime then linear
+}
+
+#[derive(Copy, Clone, Debug, Deserialize)]
+pub enum FadeMode {
+    Global,          // blend translucent black over everything every seconds_per_fade
+    Age(DecayCurve), // redraw recent segments every frame with alpha from their age
+}
+
+// The exponential curve is cut off at the end of the lifetime, this is how far it gets by then
+const EXPONENTIAL_DECAY_RATE: f64 = 5.0;
+
+// Everything in the history is drawn again on the graphics card every frame, which is cheap,
+// but it's also tessellated and sent there again because it fades (and moves in Spherical
+// and Space). However high max_history_segments is set no more than this many segments (and
+// as many polygons) are kept, which keeps that to a few megabytes a frame.
+pub const MAX_HISTORY_SEGMENTS: usize = 20000;
+
+struct Aged<T> {
+    item: T,
+    born: f64,
+}
+
+// A ring buffer of the most recently drawn segments and polygons and when they were drawn
+pub struct SegmentHistory {
+    segments: VecDeque<Aged<Segment>>,
+    polygons: VecDeque<Aged<Polygon>>,
+    clock: f64,
+}
+
+impl SegmentHistory {
+    pub fn new() -> Self {
+        SegmentHistory {
+            segments: VecDeque::new(),
+            polygons: VecDeque::new(),
+            clock: 0.0,
+        }
+    }
+
+    pub fn push(&mut self, drawing: Drawing, params: &Parameters) {
+        remember(&mut self.segments, drawing.segments, self.clock, params);
+        remember(&mut self.polygons, drawing.polygons, self.clock, params);
+    }
+
+    // Moves the clock on and forgets anything that has completely faded out
+    pub fn advance(&mut self, dt: f64, params: &Parameters) {
+        self.clock += dt;
+        forget(&mut self.segments, self.clock, params);
+        forget(&mut self.polygons, self.clock, params);
+    }
+
+    // Returns everything remembered with its alpha scaled by how old it is
+    pub fn faded(&self, curve: DecayCurve, params: &Parameters) -> Drawing {
+        let fade = |born: f64| {
+            let age = (self.clock - born) / params.segment_lifetime;
+            decay(curve, age, params) as f32
+        };
+
+        Drawing {
+            segments: self
+                .segments
+                .iter()
+                .map(|aged| {
+                    let mut segment = aged.item;
+                    segment.colour[3] *= fade(aged.born);
+                    segment
+                })
+                .collect(),
+            polygons: self
+                .polygons
+                .iter()
+                .map(|aged| {
+                    let mut polygon = aged.item.clone();
+                    polygon.colour[3] *= fade(aged.born);
+                    polygon
+                })
+                .collect(),
+        }
+    }
+}
+
+fn remember<T>(history: &mut VecDeque<Aged<T>>, items: Vec<T>, clock: f64, params: &Parameters) {
+    let most = params.max_history_segments.min(MAX_HISTORY_SEGMENTS);
+    for item in items {
+        if history.len() >= most {
+            history.pop_front();
+        }
+
+        history.push_back(Aged { item, born: clock });
+    }
+}
+
+fn forget<T>(history: &mut VecDeque<Aged<T>>, clock: f64, params: &Parameters) {
+    while let Some(oldest) = history.front() {
+        if clock - oldest.born < params.segment_lifetime {
+            break;
+        }
+
+        history.pop_front();
+    }
+}
+
+// age is 0.0 when a segment is drawn and 1.0 at the end of its lifetime
+fn decay(curve: DecayCurve, age: f64, params: &Parameters) -> f64 {
+    let age = age.clamp(0.0, 1.0);
+    match curve {
+        DecayCurve::Linear => 1.0 - age,
+        DecayCurve::Exponential => (-EXPONENTIAL_DECAY_RATE * age).exp(),
+        DecayCurve::HoldThenFade => {
+            if age < params.segment_hold {
+                1.0
+            } else {
+                1.0 - (age - params.segment_hold) / (1.0 - params.segment_hold)
+            }
+        }
+    }
+}
@@ -0,0 +1,181 @@
+use super::*;
+
+// How far the camera is from the centre of the screen as a multiple of the screen height
+const CAMERA_DISTANCE: f64 = 1.5;
+
+// Anything closer to the camera than this fraction of its distance isn't drawn
+const NEAR_PLANE: f64 = 0.05;
+
+// The dimmest that far away lines get
+const MIN_DEPTH_BRIGHTNESS: f64 = 0.15;
+
+// Which way a 3D turtle is facing. heading is the way forward, left and up complete a
+// right-handed frame (heading x left = up).
+#[derive(Copy, Clone, Debug)]
+pub struct Frame {
+    pub heading: [f64; 3],
+    pub left: [f64; 3],
+    pub up: [f64; 3],
+}
+
+impl Frame {
+    // A frame lying flat in the screen facing angle. Turning it with turn() matches how
+    // a flat turtle turns. Up points away from the viewer.
+    pub fn flat(angle: f64) -> Self {
+        Frame {
+            heading: [angle.cos(), angle.sin(), 0.0],
+            left: [-angle.sin(), angle.cos(), 0.0],
+            up: [0.0, 0.0, 1.0],
+        }
+    }
+
+    // '+' and '-', rotate about up
+    pub fn turn(&mut self, angle: f64) {
+        let (sin, cos) = angle.sin_cos();
+        let (heading, left) = (self.heading, self.left);
+        self.heading = add(scale(heading, cos), scale(left, sin));
+        self.left = add(scale(left, cos), scale(heading, -sin));
+        self.orthonormalise();
+    }
+
+    // '&' and '^', rotate about left, positive angles pitch down
+    pub fn pitch(&mut self, angle: f64) {
+        let (sin, cos) = angle.sin_cos();
+        let (heading, up) = (self.heading, self.up);
+        self.heading = add(scale(heading, cos), scale(up, -sin));
+        self.up = add(scale(up, cos), scale(heading, sin));
+        self.orthonormalise();
+    }
+
+    // Rotates the whole frame about a unit length axis
+    pub fn rotate(&mut self, axis: [f64; 3], angle: f64) {
+        let (sin, cos) = angle.sin_cos();
+        let rotate = |v: [f64; 3]| {
+            let along = scale(axis, dot(axis, v) * (1.0 - cos));
+            add(add(scale(v, cos), scale(cross(axis, v), sin)), along)
+        };
+
+        self.heading = rotate(self.heading);
+        self.left = rotate(self.left);
+        self.up = rotate(self.up);
+        self.orthonormalise();
+    }
+
+    // '\' and '/', rotate about heading, positive angles roll left
+    pub fn roll(&mut self, angle: f64) {
+        let (sin, cos) = angle.sin_cos();
+        let (left, up) = (self.left, self.up);
+        self.left = add(scale(left, cos), scale(up, sin));
+        self.up = add(scale(up, cos), scale(left, -sin));
+        self.orthonormalise();
+    }
+
+    // Rounding errors build up over thousands of turns, so after every one the axes are
+    // made unit length and at right angles to each other again
+    fn orthonormalise(&mut self) {
+        self.heading = normalise(self.heading);
+        self.left = normalise(add(
+            self.left,
+            scale(self.heading, -dot(self.heading, self.left)),
+        ));
+        self.up = cross(self.heading, self.left);
+    }
+}
+
+// Moves the pen forward through 3D space and returns where it ends up. There are no edges
+// to wrap around in space, a turtle that wanders out of sight is brought back to the middle
+// when it starts its next lsystem.
+pub fn space_forward(
+    pos: &Position,
+    distance: f64,
+    width: f64,
+    colour: [f32; 4],
+    segments: &mut Vec<Segment>,
+) -> [f64; 3] {
+    let from = [pos.x, pos.y, pos.z];
+    let to = add(from, scale(pos.frame.heading, distance));
+    segments.push(Segment {
+        from,
+        to,
+        colour,
+        width,
+    });
+
+    to
+}
+
+// Puts segments drawn in 3D onto the screen through a perspective camera that orbits
+// the centre of the screen. At rotation 0 the camera looks straight at the screen so
+// everything drawn flat in it appears just as it would in 2D. Lines further away are
+// thinner and dimmer.
+pub fn project_space(drawing: Drawing, screen: Size, rotation: f64) -> Drawing {
+    let to_screen = |p| space_to_screen(p, screen, rotation);
+
+    let segments = drawing.segments.iter().filter_map(|segment| {
+        let (from, from_perspective) = to_screen(segment.from)?;
+        let (to, to_perspective) = to_screen(segment.to)?;
+        let perspective = (from_perspective + to_perspective) / 2.0;
+        Some(Segment {
+            from,
+            to,
+            colour: depth_colour(segment.colour, perspective),
+            width: segment.width * perspective,
+        })
+    });
+
+    let polygons = drawing.polygons.iter().filter_map(|polygon| {
+        let mut points = Vec::with_capacity(polygon.points.len());
+        let mut perspective = 0.0;
+        for &point in polygon.points.iter() {
+            let (point, point_perspective) = to_screen(point)?;
+            points.push(point);
+            perspective += point_perspective / polygon.points.len() as f64;
+        }
+
+        Some(Polygon {
+            colour: depth_colour(polygon.colour, perspective),
+            ..polygon.with_points(points)
+        })
+    });
+
+    Drawing {
+        segments: segments.collect(),
+        polygons: polygons.collect(),
+    }
+}
+
+// Returns screen coordinates and how much closer than the centre of the screen the point
+// is, or None if the point is behind the camera
+pub fn space_to_screen(p: [f64; 3], screen: Size, rotation: f64) -> Option<([f64; 3], f64)> {
+    let centre = [screen.width / 2.0, screen.height / 2.0];
+    let camera_distance = screen.height * CAMERA_DISTANCE;
+    let (sin, cos) = rotation.sin_cos();
+
+    let x = p[0] - centre[0];
+    let y = p[1] - centre[1];
+    let depth = camera_distance - x * sin + p[2] * cos;
+    if depth < camera_distance * NEAR_PLANE {
+        return None;
+    }
+
+    let perspective = camera_distance / depth;
+    let x = x * cos + p[2] * sin;
+    Some((
+        [
+            centre[0] + x * perspective,
+            centre[1] + y * perspective,
+            0.0,
+        ],
+        perspective,
+    ))
+}
+
+fn depth_colour(colour: [f32; 4], perspective: f64) -> [f32; 4] {
+    let brightness = (perspective * perspective).clamp(MIN_DEPTH_BRIGHTNESS, 1.0) as f32;
+    [
+        colour[0] * brightness,
+        colour[1] * brightness,
+        colour[2] * brightness,
+        colour[3],
+    ]
+}
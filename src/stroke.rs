@@ -0,0 +1,163 @@
+use rand::prelude::*;
+use serde::Deserialize;
+use std::f64::consts::TAU;
+
+use super::*;
+
+// Pixels of line drawn then pixels left out, over and over
+const DASHES: [f64; 2] = [6.0, 4.0];
+const DOTS: [f64; 2] = [1.5, 4.5];
+
+// The longest piece of a ribbon drawn with the same width
+const RIBBON_STEP: f64 = 2.0;
+
+// How thin and how wide a ribbon gets as a multiple of the line width
+const RIBBON_MIN_WIDTH: f64 = 0.2;
+const RIBBON_MAX_WIDTH: f64 = 2.0;
+
+// Pixels from one widest point of a Ribbon to the next
+const RIBBON_WAVELENGTH: f64 = 150.0;
+
+// A CurvatureRibbon averages how much it has turned over roughly this many pixels and gets
+// thinner the more that is
+const BEND_MEMORY: f64 = 20.0;
+const BEND_THINNING: f64 = 10.0;
+
+// Lines further apart than this aren't joined so turning from one to the other doesn't count
+const JOIN_TOLERANCE: f64 = 0.5;
+
+const STROKE_STYLES: [StrokeStyle; 5] = [
+    StrokeStyle::Solid,
+    StrokeStyle::Dashed,
+    StrokeStyle::Dotted,
+    StrokeStyle::Ribbon,
+    StrokeStyle::CurvatureRibbon,
+];
+
+#[derive(Copy, Clone, Debug, Deserialize)]
+pub enum StrokeStyle {
+    Solid,           // every line drawn in full at the same width (the original look)
+    Dashed,          // dashes that carry on from one line to the next
+    Dotted,          // small dots evenly spaced along the path
+    Ribbon,          // swells and thins as the pen travels
+    CurvatureRibbon, // widest along straight runs and thinnest in the tightest turns
+    Any,             // pick one of the above at random for every system
+}
+
+// Turns the lines the pen moves along into what actually gets drawn
+#[derive(Clone)]
+pub struct Stroke {
+    style: StrokeStyle,
+    travelled: f64,        // pixels of path so far, dashes and ribbons follow this
+    bend: f64,             // radians turned per pixel lately
+    last: Option<Segment>, // to measure the next turn from
+}
+
+impl Stroke {
+    pub fn new(style: StrokeStyle, rng: &mut ThreadRng) -> Self {
+        let style = match style {
+            StrokeStyle::Any => *STROKE_STYLES.choose(rng).unwrap(),
+            style => style,
+        };
+
+        Stroke {
+            style,
+            travelled: 0.0,
+            bend: 0.0,
+            last: None,
+        }
+    }
+
+    // Adds the styled version of lines to segments. Lines are expected in the order the
+    // pen moved along them.
+    pub fn draw(&mut self, lines: Vec<Segment>, segments: &mut Vec<Segment>) {
+        for line in lines {
+            let length = distance(line.from, line.to);
+            match self.style {
+                StrokeStyle::Solid | StrokeStyle::Any => segments.push(line),
+                StrokeStyle::Dashed => self.dash(&line, length, DASHES, segments),
+                StrokeStyle::Dotted => self.dash(&line, length, DOTS, segments),
+                StrokeStyle::Ribbon | StrokeStyle::CurvatureRibbon => {
+                    self.ribbon(&line, length, segments)
+                }
+            }
+
+            self.travelled += length;
+            self.last = Some(line);
+        }
+    }
+
+    // Draws the parts of the line that fall on the drawn part of the pattern
+    fn dash(&self, line: &Segment, length: f64, pattern: [f64; 2], segments: &mut Vec<Segment>) {
+        let period = pattern[0] + pattern[1];
+        let mut from = 0.0;
+        while from < length {
+            let phase = (self.travelled + from).rem_euclid(period);
+            if phase < pattern[0] {
+                let to = (from + pattern[0] - phase).min(length);
+                segments.push(part(line, from / length, to / length));
+                from = to;
+            } else {
+                from += period - phase;
+            }
+        }
+    }
+
+    // Draws the line in short pieces that each get their own width
+    fn ribbon(&mut self, line: &Segment, length: f64, segments: &mut Vec<Segment>) {
+        if let Some(last) = self.last {
+            let joined = distance(last.to, line.from) < JOIN_TOLERANCE;
+            let before = sub(last.to, last.from);
+            let after = sub(line.to, line.from);
+            if joined && dot(before, before) > 0.0 && dot(after, after) > 0.0 {
+                let normal = cross(before, after);
+                let turn = dot(normal, normal).sqrt().atan2(dot(before, after));
+                self.bend += turn / BEND_MEMORY;
+            }
+        }
+
+        let pieces = (length / RIBBON_STEP).ceil().max(1.0);
+        let step = length / pieces;
+        for piece in 0..pieces as usize {
+            let width = match self.style {
+                StrokeStyle::Ribbon => {
+                    let wave = (TAU * (self.travelled + step * (piece as f64 + 0.5))
+                        / RIBBON_WAVELENGTH)
+                        .sin();
+                    RIBBON_MIN_WIDTH + (RIBBON_MAX_WIDTH - RIBBON_MIN_WIDTH) * (wave + 1.0) / 2.0
+                }
+                _ => {
+                    self.bend *= (-step / BEND_MEMORY).exp();
+                    RIBBON_MIN_WIDTH
+                        + (RIBBON_MAX_WIDTH - RIBBON_MIN_WIDTH) * (-self.bend * BEND_THINNING).exp()
+                }
+            };
+
+            let start = piece as f64 / pieces;
+            let end = (piece + 1) as f64 / pieces;
+            segments.push(Segment {
+                width: line.width * width,
+                ..part(line, start, end)
+            });
+        }
+    }
+}
+
+// The part of the line between the fractions from and to along it
+fn part(line: &Segment, from: f64, to: f64) -> Segment {
+    let delta = sub(line.to, line.from);
+    Segment {
+        from: add(line.from, scale(delta, from)),
+        to: add(line.from, scale(delta, to)),
+        ..*line
+    }
+}
+
+fn sub(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    add(a, scale(b, -1.0))
+}
+
+fn distance(a: [f64; 3], b: [f64; 3]) -> f64 {
+    let delta = sub(a, b);
+    dot(delta, delta).sqrt()
+}
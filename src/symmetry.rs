@@ -0,0 +1,63 @@
+use serde::Deserialize;
+use std::f64::consts::TAU;
+
+use super::*;
+
+// Copies of every segment are drawn rotated about the centre of the screen to make
+// kaleidoscope patterns out of ordinary lsystems
+#[derive(Copy, Clone, Debug, Deserialize)]
+pub enum Symmetry {
+    None,
+    Rotational(usize), // n copies evenly spaced around the centre
+    Mirrored(usize),   // as above plus a mirror image of each copy
+}
+
+// Returns the drawing along with all of its symmetrical copies
+pub fn replicate(drawing: Drawing, symmetry: Symmetry, screen: Size) -> Drawing {
+    let (folds, mirrored) = match symmetry {
+        Symmetry::None => return drawing,
+        Symmetry::Rotational(folds) => (folds.max(1), false),
+        Symmetry::Mirrored(folds) => (folds.max(1), true),
+    };
+
+    // reflecting in the horizontal line through the centre before rotating gives the mirror
+    // image of each copy
+    let flips: &[f64] = if mirrored { &[1.0, -1.0] } else { &[1.0] };
+    let copies = folds * flips.len();
+    let centre = [screen.width / 2.0, screen.height / 2.0];
+    let mut replicated = Drawing {
+        segments: Vec::with_capacity(drawing.segments.len() * copies),
+        polygons: Vec::with_capacity(drawing.polygons.len() * copies),
+    };
+
+    for fold in 0..folds {
+        let angle = TAU * fold as f64 / folds as f64;
+        let (sin, cos) = angle.sin_cos();
+        for &flip in flips {
+            let rotate = |p: [f64; 3]| {
+                let x = p[0] - centre[0];
+                let y = (p[1] - centre[1]) * flip;
+                [
+                    centre[0] + x * cos - y * sin,
+                    centre[1] + x * sin + y * cos,
+                    p[2],
+                ]
+            };
+
+            for segment in drawing.segments.iter() {
+                replicated.segments.push(Segment {
+                    from: rotate(segment.from),
+                    to: rotate(segment.to),
+                    ..*segment
+                });
+            }
+
+            for polygon in drawing.polygons.iter() {
+                let points = polygon.points.iter().map(|&p| rotate(p)).collect();
+                replicated.polygons.push(polygon.with_points(points));
+            }
+        }
+    }
+
+    replicated
+}
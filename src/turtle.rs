@@ -0,0 +1,299 @@
+use rand::prelude::*;
+use serde::Deserialize;
+use std::f64::consts::{FRAC_PI_2, TAU};
+
+use super::*;
+
+// When a turtle stops drawing and leaves the screen to make room for a new one. A turtle
+// only ever retires between lsystems so nothing is left half drawn.
+#[derive(Copy, Clone, Debug, Deserialize)]
+pub enum RetirePolicy {
+    Never,               // keep drawing new lsystems forever
+    AfterSystems(usize), // retire after drawing this many lsystems
+    AfterSeconds(f64),   // retire after the first lsystem that finishes once this old
+}
+
+// Where a turtle puts its pen down at the start of every lsystem
+#[derive(Copy, Clone, Debug, Deserialize)]
+pub enum StartPosition {
+    Continue,     // carry on from where the last lsystem ended, new turtles start at Random
+    Centre,       // the middle of the screen with a random heading
+    Random,       // anywhere on the screen with a random heading
+    LeastDense,   // somewhere in the emptiest part of the canvas with a random heading
+    BottomCentre, // the middle of the bottom edge pointing up, good for plants
+}
+
+// A single pen with its own lsystem, colours, position and speed
+pub struct Turtle {
+    turtle_states: TurtleStates,
+    current_string: Option<CurrentString>, // the substring the turtle is part way through
+    growth_fronts: GrowthFronts,           // branches growing together when not sequential
+    turtle_state: TurtleState,
+    pen_speed: f64, // pixels per second
+    systems_drawn: usize,
+    age: f64, // seconds
+    retired: bool,
+}
+
+impl Turtle {
+    pub fn new(params: &Parameters, screen: Size, canvas: &Canvas, rng: &mut ThreadRng) -> Self {
+        let mut turtle_state = TurtleState::new(params, rng);
+        let start_position = match params.start_position {
+            StartPosition::Continue => StartPosition::Random,
+            start_position => start_position,
+        };
+        place(
+            &mut turtle_state,
+            start_position,
+            params,
+            screen,
+            canvas,
+            rng,
+        );
+
+        Turtle {
+            turtle_states: TurtleStates::new(params, rng),
+            current_string: None,
+            growth_fronts: GrowthFronts::new(),
+            turtle_state,
+            pen_speed: rng.gen_range(params.min_pen_speed, params.max_pen_speed),
+            systems_drawn: 0,
+            age: 0.0,
+            retired: false,
+        }
+    }
+
+    // Where the turtle's pen is on the plane, project_point puts it on the screen
+    pub fn position(&self) -> [f64; 3] {
+        let pos = &self.turtle_state.pos;
+        [pos.x, pos.y, pos.z]
+    }
+
+    pub fn wrap_to(&mut self, screen: Size) {
+        self.turtle_state.wrap_to(screen);
+        self.growth_fronts.wrap_to(screen);
+    }
+
+    // The pen moves a set distance per second and stops wherever that gets it to, even
+    // part way along a line, so lines grow smoothly rather than popping in
+    pub fn update(
+        &mut self,
+        dt: f64,
+        params: &Parameters,
+        screen: Size,
+        canvas: &Canvas,
+        rng: &mut ThreadRng,
+        drawing: &mut Drawing,
+    ) {
+        self.age += dt;
+        let mut distance = self.pen_speed * dt;
+        match params.drawing_order {
+            DrawingOrder::Sequential => {
+                while distance > 0.0 {
+                    let current_string = match self.current_string.take() {
+                        Some(current_string) => current_string,
+                        None => match self.next_string(params, screen, canvas, rng) {
+                            Some(current_string) => current_string,
+                            None => break,
+                        },
+                    };
+
+                    let finished = draw_lsystem_substring(
+                        &current_string,
+                        &mut self.turtle_state,
+                        params,
+                        screen,
+                        &mut distance,
+                        drawing,
+                        &mut Vec::new(),
+                    );
+
+                    if !finished {
+                        self.current_string = Some(current_string);
+                    }
+                }
+            }
+            _ => {
+                while distance > 0.0 && !self.retired {
+                    if self.growth_fronts.is_empty() {
+                        let mut turtle_state = self.turtle_state.clone();
+                        turtle_state.string_pos = 0;
+                        turtle_state.segment_progress = 0.0;
+                        self.growth_fronts.start(GrowthFront {
+                            turtle_state,
+                            current_string: self.turtle_states.whole_string(),
+                        });
+                    }
+
+                    self.growth_fronts.advance(
+                        &mut distance,
+                        &mut self.turtle_state,
+                        params,
+                        screen,
+                        drawing,
+                    );
+
+                    if self.growth_fronts.is_empty() {
+                        self.new_lsystem(params, screen, canvas, rng);
+                    }
+                }
+            }
+        }
+    }
+
+    fn next_string(
+        &mut self,
+        params: &Parameters,
+        screen: Size,
+        canvas: &Canvas,
+        rng: &mut ThreadRng,
+    ) -> Option<CurrentString> {
+        loop {
+            if let Some(cs) = self.turtle_states.next() {
+                return Some(cs);
+            } else if !self.new_lsystem(params, screen, canvas, rng) {
+                return None;
+            }
+        }
+    }
+
+    // When we come to the end of our current turtle we randomly generate another one and
+    // change the pen colour. Returns false instead if it's time for this turtle to retire.
+    fn new_lsystem(
+        &mut self,
+        params: &Parameters,
+        screen: Size,
+        canvas: &Canvas,
+        rng: &mut ThreadRng,
+    ) -> bool {
+        self.systems_drawn += 1;
+        self.retired = match params.retire_policy {
+            RetirePolicy::Never => false,
+            RetirePolicy::AfterSystems(systems) => self.systems_drawn >= systems,
+            RetirePolicy::AfterSeconds(seconds) => self.age >= seconds,
+        };
+
+        if self.retired {
+            return false;
+        }
+
+        self.turtle_states = TurtleStates::new(params, rng);
+        self.turtle_state.palette = Palette::new(params.palette, rng);
+        self.turtle_state.colour = self.turtle_state.palette.pick(rng);
+        self.turtle_state.stroke = Stroke::new(params.stroke_style, rng);
+        self.turtle_state.distance_travelled = 0.0;
+        self.turtle_state.pos.width_scale = 1.0;
+        self.turtle_state.pos.length_scale = 1.0;
+        place(
+            &mut self.turtle_state,
+            params.start_position,
+            params,
+            screen,
+            canvas,
+            rng,
+        );
+        true
+    }
+}
+
+// Moves the pen to where a new lsystem should start
+fn place(
+    turtle_state: &mut TurtleState,
+    start_position: StartPosition,
+    params: &Parameters,
+    screen: Size,
+    canvas: &Canvas,
+    rng: &mut ThreadRng,
+) {
+    let pos = &mut turtle_state.pos;
+    match start_position {
+        StartPosition::Continue => {
+            // there are no edges to wrap around in space so a turtle that has wandered out
+            // of sight starts again from the middle
+            let in_sight = (0.0..=screen.width).contains(&pos.x)
+                && (0.0..=screen.height).contains(&pos.y)
+                && pos.z.abs() <= screen.height / 2.0;
+            if !matches!(params.geometry, Geometry::Space) || in_sight {
+                return;
+            }
+
+            pos.x = screen.width / 2.0;
+            pos.y = screen.height / 2.0;
+        }
+        StartPosition::Centre => {
+            pos.x = screen.width / 2.0;
+            pos.y = screen.height / 2.0;
+        }
+        StartPosition::Random => {
+            pos.x = rng.gen_range(0.0, screen.width);
+            pos.y = rng.gen_range(0.0, screen.height);
+        }
+        StartPosition::LeastDense => {
+            let point = canvas.least_dense_point(rng);
+            pos.x = point[0] * screen.width;
+            pos.y = point[1] * screen.height;
+        }
+        StartPosition::BottomCentre => {
+            pos.x = screen.width / 2.0;
+            pos.y = screen.height;
+        }
+    }
+
+    pos.angle = match start_position {
+        StartPosition::BottomCentre => -FRAC_PI_2, // y points down the screen
+        _ => rng.gen_range(0.0, TAU),
+    };
+
+    // a 3D turtle starts off lying flat in the screen
+    pos.z = 0.0;
+    pos.frame = Frame::flat(pos.angle);
+}
+
+// Every turtle on the screen. New turtles are spawned one at a time, every
+// seconds_between_spawns, until there are max_turtles of them.
+pub struct Turtles {
+    turtles: Vec<Turtle>,
+    seconds_to_next_spawn: f64,
+}
+
+impl Turtles {
+    pub fn new() -> Self {
+        Turtles {
+            turtles: Vec::new(),
+            seconds_to_next_spawn: 0.0,
+        }
+    }
+
+    // The oldest turtle that is still drawing
+    pub fn lead(&self) -> Option<[f64; 3]> {
+        self.turtles.first().map(Turtle::position)
+    }
+
+    pub fn wrap_to(&mut self, screen: Size) {
+        for turtle in self.turtles.iter_mut() {
+            turtle.wrap_to(screen);
+        }
+    }
+
+    pub fn update(
+        &mut self,
+        dt: f64,
+        params: &Parameters,
+        screen: Size,
+        canvas: &Canvas,
+        rng: &mut ThreadRng,
+        drawing: &mut Drawing,
+    ) {
+        self.seconds_to_next_spawn -= dt;
+        if self.turtles.len() < params.max_turtles && self.seconds_to_next_spawn <= 0.0 {
+            self.turtles.push(Turtle::new(params, screen, canvas, rng));
+            self.seconds_to_next_spawn = params.seconds_between_spawns;
+        }
+
+        for turtle in self.turtles.iter_mut() {
+            turtle.update(dt, params, screen, canvas, rng, drawing);
+        }
+
+        self.turtles.retain(|turtle| !turtle.retired);
+    }
+}